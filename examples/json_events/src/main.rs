@@ -17,7 +17,6 @@ use spoilers::models::*;
 use spoilers::storage::*;
 
 use chrono::NaiveDateTime;
-use rocket_contrib::JsonValue;
 
 
 #[derive(PostgreStorage)]
@@ -38,36 +37,20 @@ table! {
 
 #[derive(Resource, PgResourceStorage)]
 #[table_name="events"]
+#[deny_unknown_fields]
 pub struct Event {
     pub timestamp: NaiveDateTime,
     pub body: Option<serde_json::Value>,
 }
 
 
-#[catch(404)]
-fn not_found() -> JsonValue {
-    JsonValue(json!({
-        "status": "error",
-        "reason": "Resource was not found."
-    }))
-}
-
-
-#[catch(400)]
-fn bad_request() -> JsonValue {
-    JsonValue(json!({
-        "status": "error",
-        "reason": "Resource was not found."
-    }))
-}
-
-
 // Declare your routes here
 
 fn main() {
     let server_pool = Postgres::init_pool();
-    let server = rocket::ignite()
-        .mount("/", routes![event_create, event_get])
+    let server = Resources::<Context>::new()
+        .add::<Event>()
+        .mount(spoilers::errors::register(rocket::ignite()))
         .manage(server_pool);
     server.launch();
 }
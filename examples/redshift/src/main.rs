@@ -1,4 +1,4 @@
-#![feature(plugin, custom_attribute, custom_derive, decl_macro)]
+#![feature(plugin, attr_literals, custom_attribute, custom_derive, decl_macro)]
 #![plugin(rocket_codegen)]
 
 extern crate chrono;
@@ -49,23 +49,31 @@ table! {
 
 // Declare your models here
 
-#[derive(Resource, RedshiftResourceStorage, CollectionGet, CollectionCreate)]
-#[endpoint="/"]
+#[derive(Resource, RedshiftResourceStorage)]
+#[resource(verbs="list,create,get")]
+#[endpoint="/warning"]
 #[table_name="log_level_warning"]
 pub struct Warning {
     pub timestamp: NaiveDateTime,
     pub user_id: Option<i32>,
+    #[searchable]
+    #[validate(length(max = 64))]
     pub title: Option<String>,
+    #[searchable]
     pub body: Option<String>,
 }
 
-#[derive(Resource, RedshiftResourceStorage, CollectionGet, CollectionCreate)]
-#[endpoint="/"]
+#[derive(Resource, RedshiftResourceStorage)]
+#[resource(verbs="list,create,get")]
+#[endpoint="/error"]
 #[table_name="log_level_critical"]
 pub struct Error {
     pub timestamp: NaiveDateTime,
     pub user_id: Option<i32>,
+    #[searchable]
+    #[validate(length(max = 64))]
     pub title: Option<String>,
+    #[searchable]
     pub body: Option<String>,
 }
 
@@ -76,9 +84,10 @@ fn main() {
     let server_pool = Redshift::init_pool();
     let async_pool = Redshift::init_pool();
 
-    let server = rocket::ignite()
-        .mount("/warning", routes![warning_create, warning_get])
-        .mount("/error", routes![error_create, error_get])
+    let server = Resources::<Context>::new()
+        .add::<Warning>()
+        .add::<Error>()
+        .mount(spoilers::errors::register(rocket::ignite()))
         .manage(server_pool);
 
     Warning::sync(&async_pool, Duration::new(30 * 60, 0));
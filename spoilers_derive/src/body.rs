@@ -0,0 +1,47 @@
+use quote;
+
+use resource::MetaResourceConfig;
+
+
+impl MetaResourceConfig {
+
+    /// Whether request bodies with unknown fields are rejected, set with
+    /// `#[deny_unknown_fields]`.
+    pub fn deny_unknown_fields(&self) -> bool {
+        self.ast.attrs.iter().any(|attr| attr.name() == "deny_unknown_fields")
+    }
+
+    /// Generates the serde container attributes of forms and patches.
+    pub fn serde_options(&self) -> quote::Tokens {
+        if self.deny_unknown_fields() {
+            quote!{ #[serde(deny_unknown_fields)] }
+        } else {
+            quote!{}
+        }
+    }
+
+    /// Generates the `from_json` constructors of forms and patches, whose
+    /// errors are located at the path of the offending value.
+    pub fn impl_body_errors(&self) -> quote::Tokens {
+        let form_name = self.form_name();
+        let patch_name = self.patch_name();
+
+        quote! {
+            impl #form_name {
+                /// Reads a form from a request body.
+                pub fn from_json(document: serde_json::Value)
+                        -> Result<#form_name, spoilers::errors::BodyError> {
+                    spoilers::errors::from_value(&document)
+                }
+            }
+
+            impl #patch_name {
+                /// Reads a patch from a request body.
+                pub fn from_json(document: serde_json::Value)
+                        -> Result<#patch_name, spoilers::errors::BodyError> {
+                    spoilers::errors::from_value(&document)
+                }
+            }
+        }
+    }
+}
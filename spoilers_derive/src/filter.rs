@@ -0,0 +1,273 @@
+use syn;
+use quote;
+
+use resource::MetaResourceConfig;
+use utils::{inner_type, is_json, is_option, type_name};
+
+
+/// A field that can be filtered, with the operators its type supports.
+struct FilterField {
+    ident: syn::Ident,
+    ty: syn::Ty,
+    nullable: bool,
+    ordered: bool,
+    text: bool,
+}
+
+
+impl FilterField {
+    fn new(ident: syn::Ident, ty: &syn::Ty) -> FilterField {
+        let name = type_name(ty);
+        FilterField {
+            ident: ident,
+            ty: inner_type(ty).clone(),
+            nullable: is_option(ty),
+            // JSON documents only support equality in memory.
+            ordered: name != "Value",
+            text: name == "String",
+        }
+    }
+
+    fn operators(&self) -> Vec<quote::Tokens> {
+        let mut operators = vec![quote!{ Eq }, quote!{ Not }];
+        if self.ordered {
+            operators.extend(vec![quote!{ Min }, quote!{ Max }, quote!{ Lt }, quote!{ Gt },
+                                  quote!{ In }]);
+        }
+        if self.text {
+            operators.push(quote!{ Like });
+        }
+        if self.nullable {
+            operators.push(quote!{ Has });
+        }
+        operators.into_iter().map(|operator| {
+            quote!{ spoilers::query::Operator::#operator }
+        }).collect()
+    }
+}
+
+
+impl MetaResourceConfig {
+
+    /// Names accepted as collection filters: the key column, if any, and
+    /// every field.
+    pub fn filter_params(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.key_column().into_iter().map(|(ident, _)| {
+            ident.as_ref().to_owned()
+        }).collect();
+        names.extend(self.fields().iter().map(|field| {
+            field.ident.clone().unwrap().as_ref().to_owned()
+        }));
+        names
+    }
+
+    /// Fields that can be filtered, including the key column, if any.
+    fn filter_fields(&self) -> Vec<FilterField> {
+        let mut fields: Vec<FilterField> = self.key_column().into_iter().map(|(ident, ty)| {
+            FilterField::new(ident, &ty)
+        }).collect();
+        fields.extend(self.fields().iter().map(|field| {
+            FilterField::new(field.ident.clone().unwrap(), &field.ty)
+        }));
+        fields
+    }
+
+    /// Generates the `<Name>Filter` struct, parsed from the query string
+    /// and applied both as SQL clauses and to in-memory records.
+    pub fn impl_filter(&self) -> quote::Tokens {
+        let model_name = self.model_name();
+        let filter_name = self.filter_name();
+        let params = self.filter_params();
+        let json_fields = self.json_fields();
+        let fields = self.filter_fields();
+        let search_check = self.search_check();
+        let since_params = self.since_params();
+        let search_param = if self.searchable_fields().is_empty() {
+            quote!{}
+        } else {
+            quote!{
+                if name == "_q" {
+                    filter._search = Some(value.clone());
+                    continue;
+                }
+            }
+        };
+
+        let filter_fields: Vec<quote::Tokens> = fields.iter().map(|field| {
+            let ident = &field.ident;
+            let ty = &field.ty;
+            quote!{
+                pub #ident: Vec<spoilers::query::Condition<#ty>>,
+            }
+        }).collect();
+
+        let parse_arms: Vec<quote::Tokens> = fields.iter().map(|field| {
+            let ident = &field.ident;
+            let field_name = ident.as_ref();
+            let operators = field.operators();
+            quote!{
+                #field_name => {
+                    filter.#ident.push(spoilers::query::Condition::parse(
+                        operator, &[#(#operators),*], name, value
+                    )?);
+                },
+            }
+        }).collect();
+
+        let match_checks: Vec<quote::Tokens> = fields.iter().map(|field| {
+            let ident = &field.ident;
+            let value = if field.nullable {
+                quote!{ record.#ident.as_ref() }
+            } else {
+                quote!{ Some(&record.#ident) }
+            };
+            let check = if field.ordered {
+                quote!{ condition.matches(#value) }
+            } else {
+                quote!{ condition.matches_eq(#value) }
+            };
+            quote!{
+                if !self.#ident.iter().all(|condition| #check) {
+                    return false;
+                }
+            }
+        }).collect();
+
+        let document_arms: Vec<quote::Tokens> = self.fields().iter().filter(|field| {
+            is_json(&field.ty)
+        }).map(|field| {
+            let ident = &field.ident;
+            let name = field.ident.clone().unwrap().as_ref().to_owned();
+            if is_option(&field.ty) {
+                quote!{ #name => record.#ident.as_ref(), }
+            } else {
+                quote!{ #name => Some(&record.#ident), }
+            }
+        }).collect();
+
+        quote! {
+            #[derive(Default, Clone, Serialize, Deserialize)]
+            pub struct #filter_name {
+                #(#filter_fields)*
+                // Leading underscores keep these apart from the fields of the
+                // resource, like the reserved `_q` parameter.
+                /// Conditions on members of JSON fields, e.g. `?body.level=error`.
+                pub _json_paths: Vec<spoilers::query::PathCondition>,
+                /// Terms searched in `#[searchable]` fields, e.g. `?_q=disk full`.
+                pub _search: Option<String>,
+            }
+
+            impl #filter_name {
+                /// Parses filters from the query string, e.g. `?user_id=42`,
+                /// `?in_user_id=1,2,3`, `?gt_body.latency_ms=500` or
+                /// `?_since=1500000000000`.
+                pub fn from_query(query: &spoilers::query::Query)
+                        -> Result<#filter_name, spoilers::query::QueryError> {
+                    let fields = [#(#params),*];
+                    let json_fields: &[&str] = &[#(#json_fields),*];
+                    let mut filter = #filter_name::default();
+                    for &(ref name, ref value) in &query.params {
+                        if spoilers::query::is_list_param(name) {
+                            continue;
+                        }
+                        #search_param
+                        #since_params
+                        if let Some(condition) = spoilers::query::PathCondition::parse(
+                            name, value, json_fields
+                        )? {
+                            filter._json_paths.push(condition);
+                            continue;
+                        }
+                        let (operator, field) = match spoilers::query::Operator::split(name, &fields) {
+                            Some(split) => split,
+                            None => return Err(spoilers::query::QueryError::unknown(name, &fields)),
+                        };
+                        match field {
+                            #(#parse_arms)*
+                            _ => return Err(spoilers::query::QueryError::unknown(name, &fields)),
+                        }
+                    }
+                    Ok(filter)
+                }
+
+                /// Checks if a record matches the filters, e.g. when it
+                /// isn't stored in the database yet.
+                pub fn matches(&self, record: &#model_name) -> bool {
+                    #(#match_checks)*
+                    #search_check
+                    for condition in &self._json_paths {
+                        let document: Option<&serde_json::Value> = match condition.field.as_str() {
+                            #(#document_arms)*
+                            _ => None,
+                        };
+                        if !condition.matches(document) {
+                            return false;
+                        }
+                    }
+                    true
+                }
+            }
+        }
+    }
+
+    /// Generates the statements narrowing a boxed Diesel query, bound to
+    /// `query`, with the filters bound to `filters`. Backends without
+    /// full-text search fall back to `ILIKE` for `?_q=`.
+    pub fn filter_clauses(&self, full_text: bool) -> quote::Tokens {
+        let table_name = self.table_name();
+        let search_clause = self.search_clause(full_text);
+
+        let clauses: Vec<quote::Tokens> = self.filter_fields().iter().map(|field| {
+            let ident = &field.ident;
+            let column = quote!{ #table_name::#ident };
+
+            let mut arms = vec![
+                quote!{ Condition::Eq(ref value) => query.filter(#column.eq(value.clone())), },
+                quote!{ Condition::Not(ref value) => query.filter(#column.ne(value.clone())), },
+            ];
+            if field.ordered {
+                arms.extend(vec![
+                    quote!{ Condition::Min(ref value) => query.filter(#column.ge(value.clone())), },
+                    quote!{ Condition::Max(ref value) => query.filter(#column.le(value.clone())), },
+                    quote!{ Condition::Lt(ref value) => query.filter(#column.lt(value.clone())), },
+                    quote!{ Condition::Gt(ref value) => query.filter(#column.gt(value.clone())), },
+                    quote!{
+                        Condition::In(ref values) => query.filter(#column.eq_any(values.clone())),
+                    },
+                ]);
+            }
+            if field.text {
+                arms.push(quote!{
+                    Condition::Like(ref pattern) => query.filter(#column.like(pattern.clone())),
+                });
+            }
+            if field.nullable {
+                arms.extend(vec![
+                    quote!{ Condition::Has(true) => query.filter(#column.is_not_null()), },
+                    quote!{ Condition::Has(false) => query.filter(#column.is_null()), },
+                ]);
+            }
+
+            quote!{
+                for condition in &filters.#ident {
+                    use spoilers::query::Condition;
+                    query = match *condition {
+                        #(#arms)*
+                        // Conditions are checked against the field type when parsed.
+                        _ => query,
+                    };
+                }
+            }
+        }).collect();
+
+        quote! {
+            #(#clauses)*
+            for condition in &filters._json_paths {
+                query = query.filter(
+                    diesel::expression::dsl::sql::<diesel::types::Bool>(&condition.sql())
+                );
+            }
+            #search_clause
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use syn;
+use quote;
+
+use resource::MetaResourceConfig;
+use utils::{is_option, parse_derive_attibutes};
+
+
+/// Primary key of a resource, set with `#[primary_key(...)]`.
+pub enum PrimaryKey {
+    /// Column numbered by the database, e.g. `#[primary_key(name="id", type="i64")]`.
+    Serial { name: String, ty: String },
+    /// UUID column, e.g. `#[primary_key(name="uuid", type="Uuid")]`, generated
+    /// when records are created, or given by clients with `generate="client"`.
+    Uuid { name: String, client: bool },
+    /// Fields of the resource, e.g. `#[primary_key(fields="device_id,timestamp")]`,
+    /// declared in the same order as the primary key of the `table!`.
+    Composite { fields: Vec<String> },
+}
+
+
+impl MetaResourceConfig {
+
+    /// Primary key of the resource, a serial `id` of type `i32` by default.
+    pub fn primary_key(&self) -> PrimaryKey {
+        let attr_items = parse_derive_attibutes(self.ast.clone());
+        let items = match attr_items.get("primary_key") {
+            Some(&syn::MetaItem::List(_, ref items)) => items.clone(),
+            Some(_) => panic!("primary_key must be a list, e.g. #[primary_key(type=\"i64\")]"),
+            None => vec![],
+        };
+        let (mut name, mut ty, mut generate, mut fields) = (None, None, None, None);
+        for item in &items {
+            match *item {
+                syn::NestedMetaItem::MetaItem(
+                    syn::MetaItem::NameValue(ref option, syn::Lit::Str(ref value, _))
+                ) => match option.as_ref() {
+                    "name" => name = Some(value.clone()),
+                    "type" => ty = Some(value.clone()),
+                    "generate" => generate = Some(value.clone()),
+                    "fields" => fields = Some(value.clone()),
+                    _ => panic!("Unknown primary_key option `{}`", option),
+                },
+                _ => panic!("primary_key options must be strings, e.g. name=\"uuid\""),
+            }
+        }
+
+        let key = match fields {
+            Some(fields) => {
+                if name.is_some() || ty.is_some() || generate.is_some() {
+                    panic!("Composite primary keys only take fields");
+                }
+                let fields: Vec<String> = fields.split(',').map(|field| field.trim())
+                    .filter(|field| !field.is_empty())
+                    .map(|field| field.to_owned())
+                    .collect();
+                if fields.len() < 2 {
+                    panic!("Composite primary keys need at least two fields");
+                }
+                PrimaryKey::Composite { fields: fields }
+            },
+            None => {
+                let name = name.unwrap_or_else(|| "id".to_owned());
+                let ty = ty.unwrap_or_else(|| "i32".to_owned());
+                match ty.as_str() {
+                    "i32" | "i64" if generate.is_none() => {
+                        PrimaryKey::Serial { name: name, ty: ty.clone() }
+                    },
+                    "Uuid" => match generate.as_ref().map(|generate| generate.as_str()) {
+                        Some("client") => PrimaryKey::Uuid { name: name, client: true },
+                        Some("server") | None => PrimaryKey::Uuid { name: name, client: false },
+                        Some(generate) => {
+                            panic!("Unknown primary_key generate `{}`, expected client or server",
+                                   generate)
+                        },
+                    },
+                    "i32" | "i64" => panic!("Serial primary keys are generated by the database"),
+                    ty => panic!("Unknown primary_key type `{}`, expected i32, i64 or Uuid", ty),
+                }
+            },
+        };
+        self.check_primary_key(&key);
+        key
+    }
+
+    fn check_primary_key(&self, key: &PrimaryKey) {
+        let names: Vec<String> = self.fields().iter().map(|field| {
+            field.ident.clone().unwrap().as_ref().to_owned()
+        }).collect();
+        match *key {
+            PrimaryKey::Serial { ref name, .. } | PrimaryKey::Uuid { ref name, .. } => {
+                if names.contains(name) {
+                    panic!("The primary key `{}` is added to the model, it can't be a field",
+                           name);
+                }
+            },
+            PrimaryKey::Composite { ref fields } => {
+                for name in fields {
+                    let field = self.fields().iter().find(|field| {
+                        field.ident.as_ref().map_or(false, |ident| ident.as_ref() == name)
+                    });
+                    match field {
+                        Some(field) if is_option(&field.ty) => {
+                            panic!("Primary key field `{}` can't be nullable", name);
+                        },
+                        Some(_) => {},
+                        None => panic!("Unknown primary key field `{}`", name),
+                    }
+                }
+            },
+        }
+    }
+
+    /// Names of the primary key fields, in order.
+    pub fn key_names(&self) -> Vec<String> {
+        match self.primary_key() {
+            PrimaryKey::Serial { name, .. } | PrimaryKey::Uuid { name, .. } => vec![name],
+            PrimaryKey::Composite { fields } => fields,
+        }
+    }
+
+    /// Checks if a field of the resource is part of a composite key.
+    pub fn is_key_field(&self, name: &str) -> bool {
+        match self.primary_key() {
+            PrimaryKey::Composite { fields } => fields.iter().any(|field| field == name),
+            _ => false,
+        }
+    }
+
+    /// Column added to the model for single keys, with its type.
+    pub fn key_column(&self) -> Option<(syn::Ident, syn::Ty)> {
+        match self.primary_key() {
+            PrimaryKey::Serial { name, ty } => {
+                Some((syn::Ident::new(name), syn::parse_type(&ty).unwrap()))
+            },
+            PrimaryKey::Uuid { name, .. } => {
+                Some((syn::Ident::new(name), syn::parse_type("spoilers::keys::Uuid").unwrap()))
+            },
+            PrimaryKey::Composite { .. } => None,
+        }
+    }
+
+    /// Name of the serial key, given to the queue to number queued records.
+    pub fn serial_name(&self) -> quote::Tokens {
+        match self.primary_key() {
+            PrimaryKey::Serial { name, .. } => quote!{ Some(#name) },
+            _ => quote!{ None },
+        }
+    }
+
+    /// Name of the `<Name>Key` type alias, addressing records.
+    pub fn key_name(&self) -> syn::Ident {
+        syn::Ident::new(format!("{}Key", self.struct_name()))
+    }
+
+    /// Type of the primary key: the column type, or a tuple of the field
+    /// types of a composite key.
+    pub fn key_type(&self) -> quote::Tokens {
+        match self.key_column() {
+            Some((_, ty)) => quote!{ #ty },
+            None => {
+                let types: Vec<&syn::Ty> = self.key_names().iter().map(|name| {
+                    &self.fields().iter().find(|field| {
+                        field.ident.as_ref().map_or(false, |ident| ident.as_ref() == name)
+                    }).unwrap().ty
+                }).collect();
+                quote!{ (#(#types),*) }
+            },
+        }
+    }
+
+    /// Generates the key column of models, if any.
+    pub fn model_key_field(&self) -> quote::Tokens {
+        match self.key_column() {
+            Some((ident, ty)) => quote!{ pub #ident: #ty, },
+            None => quote!{},
+        }
+    }
+
+    /// Generates the key column of forms, for UUID keys only: serial keys are
+    /// numbered by the database, and composite keys are already fields.
+    pub fn form_key_field(&self) -> quote::Tokens {
+        match self.primary_key() {
+            PrimaryKey::Uuid { name, client: true } => {
+                let ident = syn::Ident::new(name);
+                quote!{ pub #ident: spoilers::keys::Uuid, }
+            },
+            PrimaryKey::Uuid { name, client: false } => {
+                let ident = syn::Ident::new(name);
+                quote!{
+                    #[serde(default="spoilers::keys::new_uuid")]
+                    pub #ident: spoilers::keys::Uuid,
+                }
+            },
+            _ => quote!{},
+        }
+    }
+
+    /// Checks if records are given their UUID by the server.
+    pub fn generates_key(&self) -> bool {
+        match self.primary_key() {
+            PrimaryKey::Uuid { client: false, .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Whether keys are given by the server, by the database or with
+    /// `generates_key`, so new records can't exist already.
+    pub fn assigns_key(&self) -> bool {
+        match self.primary_key() {
+            PrimaryKey::Serial { .. } | PrimaryKey::Uuid { client: false, .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Generates the statement giving a new record its server-side UUID,
+    /// bound to `form`.
+    pub fn assign_key(&self) -> quote::Tokens {
+        match self.primary_key() {
+            PrimaryKey::Uuid { name, client: false } => {
+                let ident = syn::Ident::new(name);
+                quote!{ form.#ident = spoilers::keys::new_uuid(); }
+            },
+            _ => quote!{},
+        }
+    }
+
+    /// Generates the statements keeping the key of a replaced record, with
+    /// the form bound to `form` and the key to `id`.
+    pub fn set_form_key(&self) -> quote::Tokens {
+        match self.primary_key() {
+            PrimaryKey::Serial { .. } => quote!{},
+            PrimaryKey::Uuid { name, .. } => {
+                let ident = syn::Ident::new(name);
+                quote!{ form.#ident = id.clone(); }
+            },
+            PrimaryKey::Composite { fields } => {
+                let statements: Vec<quote::Tokens> = fields.iter().enumerate().map(|(index, name)| {
+                    let ident = syn::Ident::new(name.as_str());
+                    let index = syn::Ident::new(index.to_string());
+                    quote!{ form.#ident = id.#index.clone(); }
+                }).collect();
+                quote!{ #(#statements)* }
+            },
+        }
+    }
+
+    /// Generates the statements narrowing filters to a record, with the
+    /// filters bound to `filters` and the key to `id`.
+    pub fn key_filter(&self) -> quote::Tokens {
+        let statements: Vec<quote::Tokens> = match self.key_column() {
+            Some((ident, _)) => vec![quote!{
+                filters.#ident.push(spoilers::query::Condition::Eq(id.clone()));
+            }],
+            None => self.key_names().iter().enumerate().map(|(index, name)| {
+                let ident = syn::Ident::new(name.as_str());
+                let index = syn::Ident::new(index.to_string());
+                quote!{ filters.#ident.push(spoilers::query::Condition::Eq(id.#index.clone())); }
+            }).collect(),
+        };
+        quote!{ #(#statements)* }
+    }
+
+    /// Generates the key columns, e.g. to select the keys of deleted records.
+    pub fn key_columns(&self) -> quote::Tokens {
+        let table_name = self.table_name();
+        let mut columns: Vec<quote::Tokens> = self.key_names().into_iter().map(|name| {
+            let ident = syn::Ident::new(name);
+            quote!{ #table_name::#ident }
+        }).collect();
+        if columns.len() == 1 {
+            columns.remove(0)
+        } else {
+            quote!{ (#(#columns),*) }
+        }
+    }
+
+    /// Generates the deletion of the records of the keys bound to `keys`.
+    pub fn delete_keys(&self) -> quote::Tokens {
+        let model_name = self.model_name();
+        let table_name = self.table_name();
+
+        match self.key_column() {
+            Some((ident, _)) => quote!{
+                diesel::delete(#table_name::table.filter(#table_name::#ident.eq_any(keys)))
+                    .get_results::<#model_name>(&*self.db)?
+            },
+            // Tuples can't be compared with `IN`, so records are deleted one by one.
+            None => quote!{
+                {
+                    let mut deleted: Vec<#model_name> = vec![];
+                    for key in keys {
+                        deleted.extend(diesel::delete(#table_name::table.find(key))
+                            .get_results::<#model_name>(&*self.db)?);
+                    }
+                    deleted
+                }
+            },
+        }
+    }
+
+    /// Generates the lookup of a record in the queue by the `op` queue call.
+    /// Serial keys are only given to queued records as negative ids, which
+    /// are rebound to the stored ids once synced, while other keys may be
+    /// either queued or stored.
+    pub fn queued_lookup(&self, op: quote::Tokens) -> quote::Tokens {
+        let queue_name = self.table_name().as_ref().to_owned();
+
+        match self.primary_key() {
+            PrimaryKey::Serial { .. } => quote!{
+                // Queued records are addressed by their provisional negative ids.
+                let id = if id < 0 {
+                    if let Some(model_json) = #op? {
+                        return Ok(Some(serde_json::from_value(model_json)?));
+                    }
+                    match spoilers::queue::stored_id(&*self.queue, #queue_name, id)? {
+                        Some(id) => id,
+                        None => return Ok(None),
+                    }
+                } else {
+                    id
+                };
+            },
+            _ => quote!{
+                // Queued records are not synced yet, so they are looked up first.
+                if let Some(model_json) = #op? {
+                    return Ok(Some(serde_json::from_value(model_json)?));
+                }
+            },
+        }
+    }
+
+    /// Generates the `<Name>Key` type and the helpers addressing records by
+    /// their key in paths, e.g. `/42`, `/<uuid>` or `/7,2017-10-01T00:00:00`
+    /// for a composite key.
+    pub fn impl_keys(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+        let model_name = self.model_name();
+        let key_name = self.key_name();
+        let key_type = self.key_type();
+        let key_names = self.key_names();
+        let idents: Vec<syn::Ident> = key_names.iter().map(|name| {
+            syn::Ident::new(name.as_str())
+        }).collect();
+
+        let form_name = self.form_name();
+        let form_key = match self.primary_key() {
+            PrimaryKey::Serial { .. } | PrimaryKey::Uuid { client: false, .. } => quote!{ None },
+            PrimaryKey::Uuid { .. } => quote!{ Some(form.#(#idents)*.clone()) },
+            PrimaryKey::Composite { .. } => quote!{ Some((#(form.#idents.clone()),*)) },
+        };
+
+        let (parse_key, key, key_json) = if idents.len() == 1 {
+            let name = &key_names[0];
+            let ident = &idents[0];
+            (
+                quote!{ segment.parse().ok() },
+                quote!{ record.#ident.clone() },
+                quote!{ json!({#name: key}) },
+            )
+        } else {
+            let count = idents.len();
+            let parts: Vec<quote::Tokens> = idents.iter().map(|_| {
+                quote!{ parts.next()?.parse().ok()? }
+            }).collect();
+            let members: Vec<quote::Tokens> = key_names.iter().enumerate().map(|(index, name)| {
+                let index = syn::Ident::new(index.to_string());
+                quote!{ #name: key.#index }
+            }).collect();
+            (
+                quote!{
+                    let mut parts = segment.splitn(#count, ',');
+                    Some((#(#parts),*))
+                },
+                quote!{ (#(record.#idents.clone()),*) },
+                quote!{ json!({#(#members),*}) },
+            )
+        };
+
+        quote! {
+            pub type #key_name = #key_type;
+
+            impl #struct_name {
+                /// Parses a key from a path segment.
+                pub fn parse_key(segment: &str) -> Option<#key_name> {
+                    #parse_key
+                }
+
+                /// Parses a key from a path segment, as missing if it's invalid.
+                pub fn path_key(segment: &str)
+                        -> Result<#key_name,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    #struct_name::parse_key(segment).ok_or_else(|| {
+                        ResourceStorageError::NotFound("Resource was not found.".to_owned()).into()
+                    })
+                }
+
+                pub fn key(record: &#model_name) -> #key_name {
+                    #key
+                }
+
+                /// Gets the key a new record is posted with, if clients choose it.
+                pub fn form_key(form: &#form_name) -> Option<#key_name> {
+                    #form_key
+                }
+
+                /// Renders a key as the object of its members, e.g. `{"id": 42}`.
+                pub fn key_json(key: &#key_name) -> serde_json::Value {
+                    #key_json
+                }
+
+                /// Renders the key of a deleted record.
+                pub fn tombstone(record: &#model_name) -> serde_json::Value {
+                    let mut tombstone = #struct_name::key_json(&#struct_name::key(record));
+                    tombstone["deleted"] = json!(true);
+                    tombstone
+                }
+            }
+        }
+    }
+}
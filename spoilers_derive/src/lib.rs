@@ -7,9 +7,19 @@ extern crate proc_macro;
 extern crate syn;
 extern crate spoilers;
 
+mod body;
+mod filter;
+mod keys;
+mod preconditions;
+mod projection;
 mod resource;
+mod search;
+mod sort;
 mod storage;
+mod timestamps;
 mod utils;
+mod validate;
+mod verbs;
 
 use proc_macro::TokenStream;
 
@@ -18,7 +28,9 @@ use storage::*;
 use utils::*;
 
 
-#[proc_macro_derive(Resource, attributes(endpoint))]
+#[proc_macro_derive(Resource, attributes(endpoint, resource, max_limit, searchable,
+                                         last_modified, validate, deny_unknown_fields,
+                                         primary_key))]
 pub fn derive_resource(input: TokenStream) -> TokenStream {
     MetaResourceConfig::new(parse_derive_input(input)).impl_resource().parse().unwrap()
 }
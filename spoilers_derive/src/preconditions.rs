@@ -0,0 +1,140 @@
+use quote;
+
+use resource::MetaResourceConfig;
+
+
+impl MetaResourceConfig {
+
+    /// Generates the ETag helpers of records and the checks of `If-Match`
+    /// and `If-None-Match` before writes.
+    pub fn impl_preconditions(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+        let model_name = self.model_name();
+        let get_storage = self.storage_trait("get");
+        let key_name = self.key_name();
+        let form_name = self.form_name();
+
+        // Records without a modification time are tagged by their content.
+        let (etag, version, refuse_if_match) = match self.last_modified_field() {
+            Some(field) => {
+                let ident = &field.ident;
+                (
+                    quote!{ format!("\"{}\"", record.#ident) },
+                    quote!{ Some(record.#ident) },
+                    quote!{},
+                )
+            },
+            None => (
+                quote!{ spoilers::headers::content_etag(record) },
+                quote!{ None },
+                // Without a version, storage can't compare and swap records.
+                quote!{
+                    if preconditions.if_match.is_some() {
+                        return Err(ResourceStorageError::Validation(
+                            "If-Match is not supported, as records don't track their \
+                             modification time.".to_owned()
+                        ).into());
+                    }
+                },
+            ),
+        };
+
+        // Writes read the current record, if the storage serves it.
+        let check_preconditions = if self.needs_storage("get") {
+            quote! {
+                impl #struct_name {
+                    /// Checks write preconditions against the current record,
+                    /// returning the version expected by a compare-and-swap write.
+                    pub fn check_preconditions(context: &Context, id: &#key_name,
+                                               preconditions: &spoilers::headers::Preconditions)
+                            -> Result<Option<i64>,
+                                      rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                        if preconditions.is_empty() {
+                            return Ok(None);
+                        }
+                        #refuse_if_match
+                        let current: Option<#model_name> =
+                            <Context as #get_storage>::get(context, id.clone())?;
+                        let etag = current.as_ref().map(#struct_name::etag);
+                        preconditions.check(etag.as_ref().map(|etag| etag.as_str())).map_err(|_| {
+                            ResourceStorageError::PreconditionFailed(
+                                "Resource doesn't match the request preconditions.".to_owned()
+                            )
+                        })?;
+                        if preconditions.if_match.is_some() {
+                            Ok(current.as_ref().and_then(#struct_name::version))
+                        } else {
+                            Ok(None)
+                        }
+                    }
+                }
+            }
+        } else {
+            quote!{}
+        };
+        let check_create_preconditions = if !self.needs_storage("create") {
+            quote!{}
+        } else if self.assigns_key() {
+            quote! {
+                impl #struct_name {
+                    /// Checks write preconditions of a new record, which can't
+                    /// exist already as its key isn't chosen by clients.
+                    pub fn check_create_preconditions(
+                        _context: &Context, _form: &#form_name,
+                        preconditions: &spoilers::headers::Preconditions
+                    ) -> Result<(), rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                        preconditions.check(None).map_err(|_| {
+                            ResourceStorageError::PreconditionFailed(
+                                "Resource doesn't match the request preconditions.".to_owned()
+                            ).into()
+                        })
+                    }
+                }
+            }
+        } else {
+            quote! {
+                impl #struct_name {
+                    /// Checks write preconditions of a new record, which may
+                    /// exist already at the key chosen by the client.
+                    pub fn check_create_preconditions(
+                        context: &Context, form: &#form_name,
+                        preconditions: &spoilers::headers::Preconditions
+                    ) -> Result<(), rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                        if preconditions.is_empty() {
+                            return Ok(());
+                        }
+                        match #struct_name::form_key(form) {
+                            Some(key) => {
+                                #struct_name::check_preconditions(context, &key, preconditions)
+                                    .map(|_| ())
+                            },
+                            None => preconditions.check(None).map_err(|_| {
+                                ResourceStorageError::PreconditionFailed(
+                                    "Resource doesn't match the request preconditions.".to_owned()
+                                ).into()
+                            }),
+                        }
+                    }
+                }
+            }
+        };
+
+        quote! {
+            impl #struct_name {
+                pub fn etag(record: &#model_name) -> String {
+                    #etag
+                }
+
+                /// Version compared by storage writes, if records track their
+                /// modification time.
+                pub fn version(record: &#model_name) -> Option<i64> {
+                    #version
+                }
+            }
+
+            #check_preconditions
+
+            #check_create_preconditions
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use syn;
+use quote;
+
+use resource::MetaResourceConfig;
+use utils::{is_option, type_name};
+
+
+/// Cheap SQL literal loaded in place of a column left out by `_fields`, or
+/// `None` if the column must be loaded anyway to build the model.
+fn placeholder(ty: &syn::Ty) -> Option<&'static str> {
+    if is_option(ty) {
+        return Some("NULL");
+    }
+    match type_name(ty).as_ref() {
+        "Value" => Some("'null'::jsonb"),
+        "String" => Some("''"),
+        "i16" | "i32" | "i64" | "f32" | "f64" => Some("0"),
+        "bool" => Some("FALSE"),
+        _ => None,
+    }
+}
+
+
+impl MetaResourceConfig {
+
+    /// Names accepted by `_fields`: the key column, if any, and every field.
+    pub fn projectable_fields(&self) -> Vec<String> {
+        self.filter_params()
+    }
+
+    /// Generates the selection of a listing, bound to `options`. Columns
+    /// left out by `_fields` are replaced by placeholders, so large
+    /// documents aren't loaded while the model can still be built.
+    pub fn select_clause(&self) -> quote::Tokens {
+        let table_name = self.table_name();
+
+        let columns: Vec<quote::Tokens> = self.fields().iter().map(|field| {
+            let ident = &field.ident;
+            let name = field.ident.clone().unwrap().as_ref().to_owned();
+            let column = format!("\"{}\"", name);
+            match placeholder(&field.ty) {
+                Some(placeholder) => quote!{
+                    diesel::expression::dsl::sql::< <#table_name::#ident as diesel::Expression>::SqlType >(
+                        if options.selects(#name) { #column } else { #placeholder }
+                    )
+                },
+                None => quote!{
+                    diesel::expression::dsl::sql::< <#table_name::#ident as diesel::Expression>::SqlType >(
+                        #column
+                    )
+                },
+            }
+        }).collect();
+
+        match self.key_column() {
+            Some((ident, _)) => quote!{ (#table_name::#ident, #(#columns),*) },
+            None => quote!{ (#(#columns),*) },
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use syn;
 use quote;
 
-use utils::parse_derive_attibutes;
+use keys::PrimaryKey;
+use utils::{is_json, is_option, parse_derive_attibutes};
 
 
 /// State composed of macro variables used as an util to generate
@@ -39,6 +40,34 @@ impl MetaResourceConfig {
         self.ast.ident.clone()
     }
 
+    /// Page size cap of collection listings, set with `#[max_limit="100"]`.
+    pub fn max_limit(&self) -> quote::Tokens {
+        let attr_items = parse_derive_attibutes(self.ast.clone());
+        match attr_items.get("max_limit") {
+            Some(&syn::MetaItem::NameValue(_, syn::Lit::Str(ref limit, _))) => {
+                let limit: i64 = match limit.parse() {
+                    Ok(limit) if limit > 0 => limit,
+                    _ => panic!("max_limit must be a positive integer"),
+                };
+                quote!{ #limit }
+            },
+            Some(_) => panic!("max_limit must be a string"),
+            None => quote!{ spoilers::query::DEFAULT_MAX_LIMIT },
+        }
+    }
+
+    /// Path the resource routes are mounted at, set with `#[endpoint="/warning"]`.
+    pub fn endpoint(&self) -> String {
+        let attr_items = parse_derive_attibutes(self.ast.clone());
+        match attr_items.get("endpoint") {
+            Some(&syn::MetaItem::NameValue(_, syn::Lit::Str(ref endpoint, _))) => {
+                endpoint.clone()
+            },
+            Some(_) => panic!("Endpoint must be a string"),
+            None => "/".to_owned(),
+        }
+    }
+
     pub fn model_name(&self) -> syn::Ident {
         syn::Ident::new(format!("{}Model", self.struct_name()))
     }
@@ -47,6 +76,10 @@ impl MetaResourceConfig {
         syn::Ident::new(format!("{}Form", self.struct_name()))
     }
 
+    pub fn patch_name(&self) -> syn::Ident {
+        syn::Ident::new(format!("{}Patch", self.struct_name()))
+    }
+
     pub fn filter_name(&self) -> syn::Ident {
         syn::Ident::new(format!("{}Filter", self.struct_name()))
     }
@@ -56,6 +89,31 @@ impl MetaResourceConfig {
         syn::Ident::new(format!("{}_{}", ref_name, verb))
     }
 
+    /// Storage trait of a capability, named by its first method, e.g. `list`.
+    pub fn storage_trait(&self, capability: &str) -> quote::Tokens {
+        let form_name = self.form_name();
+        let model_name = self.model_name();
+        let filter_name = self.filter_name();
+        let patch_name = self.patch_name();
+        let key_name = self.key_name();
+        match capability {
+            "create" => quote!{ spoilers::storage::CreateStorage<#form_name, #model_name> },
+            "list" => quote!{ spoilers::storage::ListStorage<#model_name, #filter_name> },
+            "get" => quote!{ spoilers::storage::GetStorage<#model_name, #key_name> },
+            "update" => quote!{
+                spoilers::storage::UpdateStorage<#form_name, #model_name, #key_name>
+            },
+            "patch" => quote!{
+                spoilers::storage::PatchStorage<#model_name, #patch_name, #key_name>
+            },
+            "delete" => quote!{ spoilers::storage::DeleteStorage<#model_name, #key_name> },
+            "delete_many" => quote!{
+                spoilers::storage::DeleteManyStorage<#model_name, #filter_name>
+            },
+            _ => panic!("Unknown storage capability `{}`", capability),
+        }
+    }
+
     pub fn fields(&self) -> &[syn::Field] {
         match self.ast.body {
             syn::Body::Struct(ref data) => data.fields(),
@@ -63,11 +121,19 @@ impl MetaResourceConfig {
         }
     }
 
+    /// Names of the fields stored as JSONB documents.
+    pub fn json_fields(&self) -> Vec<String> {
+        self.fields().iter()
+            .filter(|field| is_json(&field.ty))
+            .map(|field| field.ident.clone().unwrap().as_ref().to_owned())
+            .collect()
+    }
+
     pub fn impl_resource(&self) -> quote::Tokens {
         let struct_name = self.struct_name();
         let model_name = self.model_name();
         let form_name = self.form_name();
-        let filter_name = self.filter_name();
+        let patch_name = self.patch_name();
         let table_name = self.table_name().as_ref().to_owned();
 
         let model_fields: Vec<quote::Tokens> = self.fields().iter().map(|field| {
@@ -77,117 +143,1150 @@ impl MetaResourceConfig {
                 pub #ident: #ty,
             }
         }).collect();
+        let last_modified = self.last_modified_name();
         let form_fields: Vec<quote::Tokens> = self.fields().iter().map(|field| {
             let ident = &field.ident;
             let ty = &field.ty;
-            quote!{
-                pub #ident: #ty,
+            // The modification time is set by the storage, so clients may omit it.
+            let name = ident.clone().unwrap().as_ref().to_owned();
+            if last_modified.as_ref() == Some(&name) {
+                quote!{
+                    #[serde(default)]
+                    pub #ident: #ty,
+                }
+            } else {
+                quote!{
+                    pub #ident: #ty,
+                }
             }
         }).collect();
 
-        let collection_get = self.impl_collection_get();
-        let collection_create = self.impl_collection_create();
+        // Keys can't be patched, so composite key fields are left out.
+        let patch_only: Vec<&syn::Field> = self.fields().iter().filter(|field| {
+            !self.is_key_field(field.ident.as_ref().unwrap().as_ref())
+        }).collect();
+        let patch_fields: Vec<quote::Tokens> = patch_only.iter().map(|field| {
+            let ident = &field.ident;
+            let ty = &field.ty;
+            // Nullable fields must tell apart a missing field from `null`.
+            if is_option(ty) {
+                quote!{
+                    #[serde(default, deserialize_with="spoilers::patch::nullable",
+                            skip_serializing_if="Option::is_none")]
+                    pub #ident: Option<#ty>,
+                }
+            } else {
+                quote!{
+                    #[serde(default, skip_serializing_if="Option::is_none")]
+                    pub #ident: Option<#ty>,
+                }
+            }
+        }).collect();
+        let patch_checks: Vec<quote::Tokens> = patch_only.iter().map(|field| {
+            let ident = &field.ident;
+            quote!{ && self.#ident.is_none() }
+        }).collect();
+
+        // Only the handlers of the served verbs are generated.
+        let handler = |verb: &str, implement: &Fn() -> quote::Tokens| {
+            if self.has_verb(verb) { implement() } else { quote!{} }
+        };
+        let collection_get = handler("list", &|| self.impl_collection_get());
+        let collection_create = handler("create", &|| self.impl_collection_create());
+        let record_get = handler("get", &|| self.impl_record_get());
+        let record_update = handler("update", &|| self.impl_record_update());
+        let record_patch = handler("patch", &|| self.impl_record_patch());
+        let record_json_patch = handler("patch", &|| self.impl_record_json_patch());
+        let record_delete = handler("delete", &|| self.impl_record_delete());
+        let collection_delete = handler("delete_all", &|| self.impl_collection_delete());
+        let capabilities = self.impl_capabilities();
+        let batch_dispatch = self.impl_batch_dispatch();
+        let search = self.impl_search();
+        let preconditions = self.impl_preconditions();
+        let filter = self.impl_filter();
+        let sort = self.impl_sort();
+        let validation = self.impl_validation();
+        let endpoint = self.endpoint();
+        let route_names = self.route_names();
+        let body_errors = self.impl_body_errors();
+        let serde_options = self.serde_options();
+        let keys = self.impl_keys();
+        let model_key_field = self.model_key_field();
+        let form_key_field = self.form_key_field();
 
         quote! {
             #[derive(Queryable, Serialize, Deserialize)]
             pub struct #model_name {
-                pub id: i32,
+                #model_key_field
                 #(#model_fields)*
             }
 
-            #[derive(Insertable, Serialize, Deserialize)]
+            #[derive(Insertable, AsChangeset, Serialize, Deserialize)]
             #[table_name=#table_name]
+            #[changeset_options(treat_none_as_null="true")]
+            #serde_options
             pub struct #form_name {
+                #form_key_field
                 #(#form_fields)*
             }
 
-            #[derive(Serialize, Deserialize)]
-            pub struct #filter_name {
+            #[derive(AsChangeset, Serialize, Deserialize)]
+            #[table_name=#table_name]
+            #serde_options
+            pub struct #patch_name {
+                #(#patch_fields)*
+            }
+
+            impl #patch_name {
+                pub fn is_empty(&self) -> bool {
+                    true #(#patch_checks)*
+                }
             }
 
+            #keys
+
+            #validation
+
+            #body_errors
+
+            #filter
+
+            #sort
+
             impl Resource for #struct_name {
+                fn endpoint() -> &'static str {
+                    #endpoint
+                }
+
+                fn routes() -> Vec<rocket::Route> {
+                    routes![#(#route_names),*]
+                }
+            }
+
+            #capabilities
+
+            impl BatchResource<Context> for #struct_name {
+                fn batch(context: &Context, request: &spoilers::batch::SubRequest)
+                        -> spoilers::batch::SubResponse {
+                    #struct_name::batch(context, request)
+                }
+            }
+
+            impl #struct_name {
+                /// Mounts every route of the resource at its endpoint.
+                pub fn mount(rocket: rocket::Rocket) -> rocket::Rocket {
+                    rocket.mount(#endpoint, <#struct_name as Resource>::routes())
+                }
             }
 
             #collection_get
 
             #collection_create
+
+            #record_get
+
+            #record_update
+
+            #record_patch
+
+            #record_json_patch
+
+            #record_delete
+
+            #collection_delete
+
+            #batch_dispatch
+
+            #search
+
+            #preconditions
+
+            impl #struct_name {
+                /// Renders a request body that can't be read as a 400 error.
+                pub fn invalid_body(err: serde_json::Error)
+                        -> rocket::response::status::Custom<rocket_contrib::JsonValue> {
+                    spoilers::errors::BodyError::new(&err).response()
+                }
+
+                /// Renders a written record with its ETag. A missing record
+                /// after a compare-and-swap write means it changed meanwhile.
+                pub fn record_response(record: Option<#model_name>, version: Option<i64>)
+                        -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    match record {
+                        Some(record) => Ok(spoilers::headers::WithHeaders::new(
+                            rocket_contrib::JsonValue(json!({"data": record}))
+                        ).header("ETag", #struct_name::etag(&record))),
+                        None if version.is_some() => Err(ResourceStorageError::PreconditionFailed(
+                            "Resource was modified meanwhile.".to_owned()
+                        ).into()),
+                        None => Err(ResourceStorageError::NotFound(
+                            "Resource was not found.".to_owned()
+                        ).into()),
+                    }
+                }
+            }
         }
     }
 
     pub fn impl_collection_get(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
         let method_name = self.method_name("get");
+        let count_method_name = self.method_name("count");
         let filter_name = self.filter_name();
+        let sortable_fields = self.sortable_fields();
+        let text_fields = self.text_fields();
+        let projectable_fields = self.projectable_fields();
+        let default_sort = self.default_sort();
+        let max_limit = self.max_limit();
+        let key_names = self.key_names();
+        let list_storage = self.storage_trait("list");
 
         quote! {
+            impl #struct_name {
+                pub fn collection_get(context: &Context, query: &spoilers::query::Query,
+                                      preconditions: &spoilers::headers::Preconditions)
+                        -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    let filters = #filter_name::from_query(query).map_err(|err| err.response())?;
+                    let mut options = spoilers::query::ListOptions::from_query(
+                        query, &[#(#sortable_fields),*], #default_sort, #max_limit,
+                        &[#(#key_names),*]
+                    ).map_err(|err| err.response())?;
+                    options.fields = spoilers::query::parse_fields(
+                        query, &[#(#projectable_fields),*]
+                    ).map_err(|err| err.response())?;
+                    options.text = vec![#(#text_fields.to_owned()),*];
+                    // Counting is opt-in, as it runs a second query.
+                    let total = if query.flag("_count") {
+                        Some(<Context as #list_storage>::count(context, filters.clone())?)
+                    } else {
+                        None
+                    };
+                    let data = <Context as #list_storage>::list(context, filters, &options)?;
+
+                    // A full page may be followed by more records.
+                    let next_page = if data.len() as i64 == options.limit {
+                        data.last().map(|record| {
+                            options.next_token(&serde_json::to_value(record).unwrap())
+                        })
+                    } else {
+                        None
+                    };
+                    let data: Vec<serde_json::Value> = data.iter().map(|record| {
+                        options.project(serde_json::to_value(record).unwrap())
+                    }).collect();
+                    let mut response = spoilers::headers::WithHeaders::new(
+                        rocket_contrib::JsonValue(json!({"data": data}))
+                    );
+                    if let Some(token) = next_page {
+                        response = response.header("Next-Page", token);
+                    }
+                    if let Some(total) = total {
+                        response = response.header("Total-Records", total);
+                    }
+                    let version = <Context as #list_storage>::timestamp(context)?;
+                    if let Some((timestamp, count)) = version {
+                        let etag = format!("\"{}-{}\"", timestamp, count);
+                        if preconditions.is_not_modified(&etag) {
+                            response = response.status(rocket::http::Status::NotModified);
+                        }
+                        response = response.header("ETag", etag);
+                    }
+                    Ok(response)
+                }
+
+                pub fn collection_count(context: &Context, query: &spoilers::query::Query)
+                        -> Result<spoilers::headers::WithHeaders<()>,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    let filters = #filter_name::from_query(query).map_err(|err| err.response())?;
+                    let total = <Context as #list_storage>::count(context, filters)?;
+                    Ok(spoilers::headers::WithHeaders::new(()).header("Total-Records", total))
+                }
+            }
+
             #[get("/", format = "application/json")]
-            fn #method_name(context: Context) -> rocket_contrib::JsonValue {
-                let data = context.list(#filter_name {});
-                rocket_contrib::JsonValue(json!({"data": data.expect("error")}))
+            fn #method_name(query: spoilers::query::Query,
+                            preconditions: spoilers::headers::Preconditions, context: Context)
+                    -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                              rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                #struct_name::collection_get(&context, &query, &preconditions)
+            }
+
+            #[head("/")]
+            fn #count_method_name(query: spoilers::query::Query, context: Context)
+                    -> Result<spoilers::headers::WithHeaders<()>,
+                              rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                #struct_name::collection_count(&context, &query)
             }
         }
     }
 
 
     pub fn impl_collection_create(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
         let method_name = self.method_name("create");
         let form_name = self.form_name();
+        let model_name = self.model_name();
+        let create_storage = self.storage_trait("create");
+        let assign_key = self.assign_key();
+        let assign_keys = if self.generates_key() {
+            quote!{
+                for form in new.iter_mut() {
+                    #assign_key
+                }
+            }
+        } else {
+            quote!{}
+        };
 
         quote! {
-            #[post("/", format = "application/json", data = "<message>")]
-            fn #method_name(message: rocket_contrib::Json<serde_json::Value>, context: Context)
-                    -> Result<rocket_contrib::JsonValue, rocket::response::Failure> {
-                let new: #form_name = match serde_json::from_value(message.0) {
+            impl #struct_name {
+                pub fn collection_create(context: &Context, message: serde_json::Value,
+                                         query: &spoilers::query::Query,
+                                         preconditions: &spoilers::headers::Preconditions)
+                        -> Result<rocket::response::status::Custom<rocket_contrib::JsonValue>,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    use rocket::http::Status;
+                    use rocket::response::status::Custom;
+
+                    // Partial mode stores every valid item and reports each one.
+                    if query.flag("_partial") && message.is_array() {
+                        let items = match message {
+                            serde_json::Value::Array(items) => items,
+                            _ => vec![],
+                        };
+                        let results: Vec<serde_json::Value> = items.into_iter().enumerate()
+                                .map(|(index, item)| {
+                            #[allow(unused_mut)]
+                            let mut form = match #form_name::from_json(item) {
+                                Ok(v) => v,
+                                Err(err) => {
+                                    return json!({
+                                        "index": index, "status": 400, "error": err.body()
+                                    });
+                                }
+                            };
+                            #assign_key
+                            if let Err(errors) = form.validate() {
+                                return json!({
+                                    "index": index, "status": 422, "error": errors.body()
+                                });
+                            }
+                            if let Err(Custom(status, body)) =
+                                    #struct_name::check_create_preconditions(context, &form,
+                                                                             preconditions) {
+                                return json!({
+                                    "index": index, "status": status.code, "error": body.0
+                                });
+                            }
+                            match <Context as #create_storage>::create(context, form) {
+                                Ok(created) => {
+                                    json!({"index": index, "status": 201, "data": created})
+                                },
+                                Err(err) => json!({
+                                    "index": index, "status": err.status().code,
+                                    "error": err.body()
+                                }),
+                            }
+                        }).collect();
+                        return Ok(Custom(Status::MultiStatus,
+                                         rocket_contrib::JsonValue(json!({"data": results}))));
+                    }
+
+                    if message.is_array() {
+                        let items = match message {
+                            serde_json::Value::Array(items) => items,
+                            _ => vec![],
+                        };
+                        let mut new: Vec<#form_name> = vec![];
+                        for (index, item) in items.into_iter().enumerate() {
+                            new.push(#form_name::from_json(item).map_err(|err| {
+                                err.at(&index.to_string())
+                            })?);
+                        }
+                        #assign_keys
+                        for (index, form) in new.iter().enumerate() {
+                            if let Err(errors) = form.validate() {
+                                let body = errors.body_with(json!({"index": index}));
+                                return Err(Custom(Status::UnprocessableEntity,
+                                                  rocket_contrib::JsonValue(body)));
+                            }
+                        }
+                        for form in new.iter() {
+                            #struct_name::check_create_preconditions(context, form,
+                                                                     preconditions)?;
+                        }
+                        let created: Vec<#model_name> =
+                            <Context as #create_storage>::bulk_create(context, new)?;
+                        return Ok(Custom(Status::Ok,
+                                         rocket_contrib::JsonValue(json!({"data": created}))));
+                    }
+
+                    #[allow(unused_mut)]
+                    let mut form = #form_name::from_json(message)?;
+                    #assign_key
+                    form.validate()?;
+                    #struct_name::check_create_preconditions(context, &form, preconditions)?;
+                    let created = match <Context as #create_storage>::create(context, form) {
+                        // A record created meanwhile fails `If-None-Match: *`.
+                        Err(ResourceStorageError::Conflict(_))
+                                if preconditions.is_create_only() => {
+                            return Err(ResourceStorageError::PreconditionFailed(
+                                "Resource doesn't match the request preconditions.".to_owned()
+                            ).into());
+                        },
+                        result => result?,
+                    };
+                    Ok(Custom(Status::Ok, rocket_contrib::JsonValue(json!({"data": created}))))
+                }
+            }
+
+            #[post("/", format = "application/json", data = "<data>")]
+            fn #method_name(data: rocket::Data, query: spoilers::query::Query,
+                            preconditions: spoilers::headers::Preconditions, context: Context)
+                    -> Result<rocket::response::status::Custom<rocket_contrib::JsonValue>,
+                              rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                let message: serde_json::Value = match serde_json::from_reader(data.open()) {
                     Ok(v) => v,
-                    Err(_) => {
-                        return Err(rocket::response::Failure(rocket::http::Status::BadRequest));
+                    Err(err) => {
+                        return Err(#struct_name::invalid_body(err));
                     }
                 };
-                let created = context.create(new);
-                Ok(rocket_contrib::JsonValue(json!({"data": created.expect("error")})))
+                #struct_name::collection_create(&context, message, &query, &preconditions)
             }
         }
     }
 
 
-    pub fn impl_pg_storage_backend(&self) -> quote::Tokens {
+    pub fn impl_record_get(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+        let method_name = self.method_name("record_get");
+        let model_name = self.model_name();
+        let filter_name = self.filter_name();
+        let list_storage = self.storage_trait("list");
+        let get_storage = self.storage_trait("get");
+        let projectable_fields = self.projectable_fields();
+        let key_name = self.key_name();
+        let key_names = self.key_names();
+        let key_filter = self.key_filter();
+        let version = match self.last_modified_name() {
+            Some(name) => quote!{ Some(#name.to_owned()) },
+            None => quote!{ None },
+        };
+
+        quote! {
+            impl #struct_name {
+                pub fn record_get(context: &Context, id: #key_name,
+                                  query: &spoilers::query::Query,
+                                  preconditions: &spoilers::headers::Preconditions)
+                        -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    let mut options = spoilers::query::ListOptions::default();
+                    options.primary_key = [#(#key_names),*].iter().map(|name| {
+                        name.to_string()
+                    }).collect();
+                    options.version = #version;
+                    options.fields = spoilers::query::parse_fields(
+                        query, &[#(#projectable_fields),*]
+                    ).map_err(|err| err.response())?;
+
+                    // Projections are served by the listing query, narrowed to the key.
+                    // Records tagged by their content are read whole, so their ETag
+                    // doesn't depend on the projection.
+                    let record: Option<#model_name> =
+                            if options.fields.is_some() && options.version.is_some() {
+                        let mut filters = #filter_name::default();
+                        #key_filter
+                        options.limit = 1;
+                        <Context as #list_storage>::list(context, filters, &options)?.pop()
+                    } else {
+                        <Context as #get_storage>::get(context, id)?
+                    };
+                    match record {
+                        Some(record) => {
+                            let etag = #struct_name::etag(&record);
+                            let data = options.project(serde_json::to_value(&record).unwrap());
+                            let mut response = spoilers::headers::WithHeaders::new(
+                                rocket_contrib::JsonValue(json!({"data": data}))
+                            );
+                            if preconditions.is_not_modified(&etag) {
+                                response = response.status(rocket::http::Status::NotModified);
+                            }
+                            Ok(response.header("ETag", etag))
+                        },
+                        None => Err(ResourceStorageError::NotFound(
+                            "Resource was not found.".to_owned()
+                        ).into()),
+                    }
+                }
+            }
+
+            #[get("/<id>", format = "application/json")]
+            fn #method_name(id: String, query: spoilers::query::Query,
+                            preconditions: spoilers::headers::Preconditions, context: Context)
+                    -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                              rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                #struct_name::record_get(&context, #struct_name::path_key(&id)?, &query,
+                                         &preconditions)
+            }
+        }
+    }
+
+
+    pub fn impl_record_update(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+        let method_name = self.method_name("update");
         let form_name = self.form_name();
         let model_name = self.model_name();
+        let create_storage = self.storage_trait("create");
+        let update_storage = self.storage_trait("update");
+        let key_name = self.key_name();
+        let set_form_key = self.set_form_key();
+
+        // Records numbered by the database can't be created at a given path.
+        let create_if_absent = match self.primary_key() {
+            PrimaryKey::Serial { .. } => quote!{},
+            _ => quote! {
+                // `If-None-Match: *` only passed if there is no record yet.
+                if preconditions.is_create_only() {
+                    let created = match <Context as #create_storage>::create(context, form) {
+                        Err(ResourceStorageError::Conflict(_)) => {
+                            return Err(ResourceStorageError::PreconditionFailed(
+                                "Resource doesn't match the request preconditions.".to_owned()
+                            ).into());
+                        },
+                        result => result?,
+                    };
+                    return Ok(spoilers::headers::WithHeaders::new(
+                        rocket_contrib::JsonValue(json!({"data": created}))
+                    ).status(rocket::http::Status::Created)
+                     .header("ETag", #struct_name::etag(&created)));
+                }
+            },
+        };
+
+        quote! {
+            impl #struct_name {
+                pub fn record_update(context: &Context, id: #key_name, message: serde_json::Value,
+                                     preconditions: &spoilers::headers::Preconditions)
+                        -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    #[allow(unused_mut)]
+                    let mut form = #form_name::from_json(message)?;
+                    // The key is given by the path, replacing the one of the body.
+                    #set_form_key
+                    form.validate()?;
+                    let version = #struct_name::check_preconditions(context, &id, preconditions)?;
+                    #create_if_absent
+                    let updated: Option<#model_name> =
+                        <Context as #update_storage>::update(context, id, form, version)?;
+                    #struct_name::record_response(updated, version)
+                }
+            }
+
+            #[put("/<id>", format = "application/json", data = "<data>")]
+            fn #method_name(id: String, data: rocket::Data,
+                            preconditions: spoilers::headers::Preconditions, context: Context)
+                    -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                              rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                let message: serde_json::Value = match serde_json::from_reader(data.open()) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        return Err(#struct_name::invalid_body(err));
+                    }
+                };
+                #struct_name::record_update(&context, #struct_name::path_key(&id)?, message,
+                                            &preconditions)
+            }
+        }
+    }
+
+
+    pub fn impl_record_patch(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+        let method_name = self.method_name("patch");
+        let patch_name = self.patch_name();
+        let model_name = self.model_name();
+        let get_storage = self.storage_trait("get");
+        let patch_storage = self.storage_trait("patch");
+        let key_name = self.key_name();
+
+        quote! {
+            impl #struct_name {
+                pub fn record_patch(context: &Context, id: #key_name,
+                                    mut changes: serde_json::Value,
+                                    preconditions: &spoilers::headers::Preconditions)
+                        -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    if !changes.is_object() {
+                        return Err(ResourceStorageError::Validation(
+                            "Merge patches must be objects.".to_owned()
+                        ).into());
+                    }
+                    let version = #struct_name::check_preconditions(context, &id, preconditions)?;
+                    // Objects are merged recursively, so they need the current record.
+                    if spoilers::patch::has_nested(&changes) {
+                        let current: Option<#model_name> =
+                            <Context as #get_storage>::get(context, id.clone())?;
+                        match current {
+                            Some(current) => spoilers::patch::merge_nested(
+                                &mut changes, &serde_json::to_value(&current).unwrap()
+                            ),
+                            None => {
+                                return Err(ResourceStorageError::NotFound(
+                                    "Resource was not found.".to_owned()
+                                ).into());
+                            }
+                        }
+                    }
+                    let patch = #patch_name::from_json(changes)?;
+                    patch.validate()?;
+                    let patched: Option<#model_name> =
+                        <Context as #patch_storage>::patch(context, id, patch, version)?;
+                    #struct_name::record_response(patched, version)
+                }
+            }
+
+            #[patch("/<id>", format = "application/merge-patch+json", data = "<data>")]
+            fn #method_name(id: String, data: rocket::Data,
+                            preconditions: spoilers::headers::Preconditions, context: Context)
+                    -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                              rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                let changes: serde_json::Value = match serde_json::from_reader(data.open()) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        return Err(#struct_name::invalid_body(err));
+                    }
+                };
+                #struct_name::record_patch(&context, #struct_name::path_key(&id)?, changes,
+                                           &preconditions)
+            }
+        }
+    }
+
+
+    pub fn impl_record_json_patch(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+        let method_name = self.method_name("json_patch");
+        let form_name = self.form_name();
+        let model_name = self.model_name();
+        let patch_storage = self.storage_trait("patch");
+        let get_storage = self.storage_trait("get");
+        let update_storage = self.storage_trait("update");
+        // Validated documents are patched in memory, to be checked before stored.
+        let validated = self.validated_names();
+        let json_fields: Vec<String> = self.json_fields().into_iter()
+            .filter(|name| !validated.contains(name))
+            .collect();
+        let key_name = self.key_name();
+        // Serial keys aren't form fields, other keys are kept by updates.
+        let strip_serial = match self.primary_key() {
+            PrimaryKey::Serial { name, .. } => quote!{
+                if let Some(members) = document.as_object_mut() {
+                    members.remove(#name);
+                }
+            },
+            _ => quote!{},
+        };
+
+        quote! {
+            impl #struct_name {
+                pub fn record_json_patch(context: &Context, id: #key_name,
+                                         operations: serde_json::Value,
+                                         preconditions: &spoilers::headers::Preconditions)
+                        -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    use spoilers::patch::PatchError;
+
+                    let operations: Vec<spoilers::patch::Operation> =
+                            match serde_json::from_value(operations) {
+                        Ok(v) => v,
+                        Err(err) => return Err(#struct_name::invalid_body(err)),
+                    };
+                    let version = #struct_name::check_preconditions(context, &id, preconditions)?;
+
+                    let patched: Option<#model_name> =
+                            if spoilers::patch::is_atomic(&operations, &[#(#json_fields),*]) {
+                        <Context as #patch_storage>::json_patch(context, id, operations, version)?
+                    } else {
+                        let current: Option<#model_name> =
+                            <Context as #get_storage>::get(context, id.clone())?;
+                        let mut document = match current {
+                            Some(current) => serde_json::to_value(&current).unwrap(),
+                            None => {
+                                return Err(ResourceStorageError::NotFound(
+                                    "Resource was not found.".to_owned()
+                                ).into());
+                            },
+                        };
+                        match spoilers::patch::apply(&mut document, &operations) {
+                            Ok(()) => {},
+                            Err(PatchError::TestFailed(path)) => {
+                                return Err(ResourceStorageError::Conflict(
+                                    format!("Test failed at `{}`.", path)
+                                ).into());
+                            },
+                            Err(PatchError::InvalidPath(path)) => {
+                                return Err(ResourceStorageError::Unprocessable(
+                                    format!("Invalid path `{}`.", path)
+                                ).into());
+                            },
+                        }
+                        if !spoilers::keys::matches(&document, &#struct_name::key_json(&id)) {
+                            return Err(ResourceStorageError::Unprocessable(
+                                "The record key can't be changed.".to_owned()
+                            ).into());
+                        }
+                        #strip_serial
+                        let form: #form_name = match serde_json::from_value(document) {
+                            Ok(v) => v,
+                            Err(err) => {
+                                return Err(ResourceStorageError::Unprocessable(err.to_string())
+                                    .into());
+                            },
+                        };
+                        form.validate()?;
+                        <Context as #update_storage>::update(context, id, form, version)?
+                    };
+                    #struct_name::record_response(patched, version)
+                }
+            }
+
+            #[patch("/<id>", format = "application/json-patch+json", data = "<data>")]
+            fn #method_name(id: String, data: rocket::Data,
+                            preconditions: spoilers::headers::Preconditions, context: Context)
+                    -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                              rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                let operations: serde_json::Value = match serde_json::from_reader(data.open()) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        return Err(#struct_name::invalid_body(err));
+                    }
+                };
+                #struct_name::record_json_patch(&context, #struct_name::path_key(&id)?, operations,
+                                                &preconditions)
+            }
+        }
+    }
+
+
+    pub fn impl_record_delete(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+        let method_name = self.method_name("delete");
+        let model_name = self.model_name();
+        let delete_storage = self.storage_trait("delete");
+        let key_name = self.key_name();
+
+        quote! {
+            impl #struct_name {
+                pub fn record_delete(context: &Context, id: #key_name,
+                                     preconditions: &spoilers::headers::Preconditions)
+                        -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    let version = #struct_name::check_preconditions(context, &id, preconditions)?;
+                    let deleted: Option<#model_name> =
+                        <Context as #delete_storage>::delete(context, id, version)?;
+                    match deleted {
+                        Some(record) => Ok(spoilers::headers::WithHeaders::new(
+                            rocket_contrib::JsonValue(json!({
+                                "data": #struct_name::tombstone(&record)
+                            }))
+                        ).header("ETag", #struct_name::etag(&record))),
+                        // A version mismatch means the record changed since it was checked.
+                        None if version.is_some() => Err(ResourceStorageError::PreconditionFailed(
+                            "Resource was modified meanwhile.".to_owned()
+                        ).into()),
+                        None => Err(ResourceStorageError::NotFound(
+                            "Resource was not found.".to_owned()
+                        ).into()),
+                    }
+                }
+            }
+
+            #[delete("/<id>", format = "application/json")]
+            fn #method_name(id: String, preconditions: spoilers::headers::Preconditions,
+                            context: Context)
+                    -> Result<spoilers::headers::WithHeaders<rocket_contrib::JsonValue>,
+                              rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                #struct_name::record_delete(&context, #struct_name::path_key(&id)?, &preconditions)
+            }
+        }
+    }
+
+
+    pub fn impl_collection_delete(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+        let method_name = self.method_name("collection_delete");
+        let model_name = self.model_name();
         let filter_name = self.filter_name();
-        let table_name = self.table_name();
+        let delete_many_storage = self.storage_trait("delete_many");
 
         quote! {
+            impl #struct_name {
+                pub fn collection_delete(context: &Context, query: &spoilers::query::Query)
+                        -> Result<rocket_contrib::JsonValue,
+                                  rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                    query.deny_list_params().map_err(|err| err.response())?;
+                    let filters = #filter_name::from_query(query).map_err(|err| err.response())?;
+                    let deleted: Vec<#model_name> =
+                        <Context as #delete_many_storage>::delete_many(context, filters)?;
+                    let tombstones: Vec<serde_json::Value> = deleted.iter()
+                        .map(#struct_name::tombstone)
+                        .collect();
+                    Ok(rocket_contrib::JsonValue(json!({"data": tombstones})))
+                }
+            }
 
-            impl spoilers::storage::ResourceStorage<#form_name,#model_name,#filter_name>
-                    for Context {
+            #[delete("/", format = "application/json")]
+            fn #method_name(query: spoilers::query::Query, context: Context)
+                    -> Result<rocket_contrib::JsonValue,
+                              rocket::response::status::Custom<rocket_contrib::JsonValue>> {
+                #struct_name::collection_delete(&context, &query)
+            }
+        }
+    }
 
-                fn create<'a>(&self, form: #form_name) ->
-                        Result<#model_name, ResourceStorageError> {
 
-                    let created: #model_name = diesel::insert(&form).into(#table_name::table)
-                        .get_result(&*self.db)
-                        .expect("Error saving new post");
-                    Ok(created)
+    /// Dispatches sub-requests of a `POST /batch` to the resource handlers.
+    /// Sub-request paths are relative to the resource endpoint.
+    pub fn impl_batch_dispatch(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+        let key_name = self.key_name();
+
+        let mut arms: Vec<quote::Tokens> = vec![];
+        if self.has_verb("list") {
+            arms.push(quote! {
+                ("GET", None) => {
+                    #struct_name::collection_get(context, &query, &preconditions).into()
+                },
+                ("HEAD", None) => #struct_name::collection_count(context, &query).into(),
+            });
+        }
+        if self.has_verb("create") {
+            arms.push(quote! {
+                ("POST", None) => {
+                    #struct_name::collection_create(context, body, &query, &preconditions).into()
+                },
+            });
+        }
+        if self.has_verb("delete_all") {
+            arms.push(quote! {
+                ("DELETE", None) => #struct_name::collection_delete(context, &query).into(),
+            });
+        }
+        if self.has_verb("get") {
+            arms.push(quote! {
+                ("GET", Some(id)) => {
+                    #struct_name::record_get(context, id, &query, &preconditions).into()
+                },
+            });
+        }
+        if self.has_verb("update") {
+            arms.push(quote! {
+                ("PUT", Some(id)) => {
+                    #struct_name::record_update(context, id, body, &preconditions).into()
+                },
+            });
+        }
+        if self.has_verb("patch") {
+            arms.push(quote! {
+                // JSON Patch documents are arrays, merge patches are objects.
+                ("PATCH", Some(id)) => if body.is_array() {
+                    #struct_name::record_json_patch(context, id, body, &preconditions).into()
+                } else {
+                    #struct_name::record_patch(context, id, body, &preconditions).into()
+                },
+            });
+        }
+        if self.has_verb("delete") {
+            arms.push(quote! {
+                ("DELETE", Some(id)) => {
+                    #struct_name::record_delete(context, id, &preconditions).into()
+                },
+            });
+        }
+
+        quote! {
+            impl #struct_name {
+                // Arguments are left unused by resources serving few verbs.
+                #[allow(unused_variables)]
+                pub fn batch(context: &Context, request: &spoilers::batch::SubRequest)
+                        -> spoilers::batch::SubResponse {
+                    use spoilers::batch::SubResponse;
+                    use rocket::http::Status;
+
+                    let (path, query) = request.split_query();
+                    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+                    let id: Option<#key_name> = match segments.len() {
+                        0 => None,
+                        1 => match #struct_name::parse_key(segments[0]) {
+                            Some(id) => Some(id),
+                            None => return SubResponse::error(Status::NotFound),
+                        },
+                        _ => return SubResponse::error(Status::NotFound),
+                    };
+                    let body = request.body.clone().unwrap_or(serde_json::Value::Null);
+                    let preconditions = request.preconditions();
+
+                    match (request.method.to_uppercase().as_str(), id) {
+                        #(#arms)*
+                        _ => SubResponse::error(Status::MethodNotAllowed),
+                    }
                 }
+            }
+        }
+    }
 
-                fn bulk_create<'a>(&self, form: Vec<#form_name>) ->
+
+    pub fn impl_pg_storage_backend(&self) -> quote::Tokens {
+        let form_name = self.form_name();
+        let model_name = self.model_name();
+        let patch_name = self.patch_name();
+        let filter_name = self.filter_name();
+        let table_name = self.table_name();
+        let get_storage = self.storage_trait("get");
+        let filter_clauses = self.filter_clauses(true);
+        let select_clause = self.select_clause();
+        let touch_form = self.touch_form(false);
+        let touch_patch = self.touch_patch(false);
+        let timestamp = self.impl_timestamp(false);
+        let lock_versions = self.lock_versions();
+        let version_condition = self.version_condition();
+        let key_name = self.key_name();
+        let key_type = self.key_type();
+        let key_columns = self.key_columns();
+        let delete_keys = self.delete_keys();
+        // Touching the record first also checks its version.
+        let touch_json_patch = match self.last_modified_field() {
+            Some(field) => {
+                let ident = &field.ident;
+                quote!{
+                    #lock_versions
+                    let target = #table_name::table.find(id.clone()).filter(#version_condition);
+                    let touched = diesel::update(target)
+                        .set(#table_name::#ident.eq(spoilers::storage::next_version(latest)))
+                        .execute(&*self.db)?;
+                    if touched == 0 {
+                        return Ok(None);
+                    }
+                }
+            },
+            None => quote!{},
+        };
+
+        let json_patch_arms: Vec<quote::Tokens> = self.fields().iter().filter(|field| {
+            is_json(&field.ty)
+        }).map(|field| {
+            let ident = &field.ident;
+            let name = field.ident.clone().unwrap().as_ref().to_owned();
+            let functions = if is_option(&field.ty) {
+                quote!{ spoilers::patch::sql::nullable_jsonb }
+            } else {
+                quote!{ spoilers::patch::sql::jsonb }
+            };
+            quote!{
+                #name => {
+                    let guard = spoilers::patch::guard_sql(#name, operation, &path);
+                    let target = target.filter(
+                        diesel::expression::dsl::sql::<diesel::types::Bool>(&guard)
+                    );
+                    match *operation {
+                        Operation::Remove { .. } => {
+                            diesel::update(target)
+                                .set(#table_name::#ident.eq(
+                                    #functions::jsonb_delete_path(#table_name::#ident, path)
+                                ))
+                                .execute(&*self.db)?
+                        },
+                        Operation::Add { ref value, .. } |
+                        Operation::Replace { ref value, .. } => {
+                            diesel::update(target)
+                                .set(#table_name::#ident.eq(
+                                    #functions::jsonb_set(#table_name::#ident, path, value, true)
+                                ))
+                                .execute(&*self.db)?
+                        },
+                        _ => 1,
+                    }
+                },
+            }
+        }).collect();
+
+        let create = self.versioned(quote!{
+            #touch_form
+            diesel::insert(&form).into(#table_name::table)
+                .get_result::<#model_name>(&*self.db)
+                .map_err(ResourceStorageError::from)
+        });
+        let bulk_create = self.versioned(quote!{
+            for form in forms.iter_mut() {
+                #touch_form
+            }
+            let created: Vec<#model_name> = diesel::insert(&forms).into(#table_name::table)
+                .get_results(&*self.db)?;
+            Ok(created)
+        });
+        let update = self.versioned(quote!{
+            #touch_form
+            let target = #table_name::table.find(id).filter(#version_condition);
+            let updated = diesel::update(target)
+                .set(&form)
+                .get_result::<#model_name>(&*self.db)
+                .optional()?;
+            Ok(updated)
+        });
+        let patch = self.versioned(quote!{
+            #touch_patch
+            let target = #table_name::table.find(id).filter(#version_condition);
+            let patched = diesel::update(target)
+                .set(&patch)
+                .get_result::<#model_name>(&*self.db)
+                .optional()?;
+            Ok(patched)
+        });
+
+        let storage_impls = self.storage_impls(vec![
+            ("create", quote! {
+                fn create<'a>(&self, mut form: #form_name) ->
                         Result<#model_name, ResourceStorageError> {
 
-                    let created: #model_name = diesel::insert(&form).into(#table_name::table)
-                        .get_result(&*self.db)
-                        .expect("Error saving new post");
-                    Ok(created)
+                    #create
                 }
+            }),
+            ("bulk_create", quote! {
+                fn bulk_create<'a>(&self, mut forms: Vec<#form_name>) ->
+                        Result<Vec<#model_name>, ResourceStorageError> {
 
-                fn list<'a>(&self, _filters: #filter_name) ->
+                    #bulk_create
+                }
+            }),
+            ("list", quote! {
+                fn list<'a>(&self, filters: #filter_name, options: &spoilers::query::ListOptions) ->
                         Result<Vec<#model_name>, ResourceStorageError> {
 
-                    use #table_name::dsl::*;
-                    let results = #table_name.limit(10)
-                        .load::<#model_name>(&*self.db)
-                        .expect("Error loading events");
+                    let mut query = #table_name::table.select(#select_clause).into_boxed();
+                    #filter_clauses
+                    if let Some(after) = options.after_sql() {
+                        query = query.filter(diesel::expression::dsl::sql::<diesel::types::Bool>(&after));
+                    }
+                    let results = query
+                        .order(diesel::expression::dsl::sql::<diesel::types::Bool>(&options.order_by()))
+                        .limit(options.limit)
+                        .load::<#model_name>(&*self.db)?;
                     Ok(results)
                 }
-            }
+            }),
+            ("count", quote! {
+                fn count<'a>(&self, filters: #filter_name) ->
+                        Result<i64, ResourceStorageError> {
+
+                    let mut query = #table_name::table
+                        .select(diesel::expression::dsl::count_star())
+                        .into_boxed();
+                    #filter_clauses
+                    let count = query.first::<i64>(&*self.db)?;
+                    Ok(count)
+                }
+            }),
+            ("timestamp", timestamp),
+            ("get", quote! {
+                fn get<'a>(&self, id: #key_name) ->
+                        Result<Option<#model_name>, ResourceStorageError> {
+
+                    let record = #table_name::table.find(id)
+                        .first::<#model_name>(&*self.db)
+                        .optional()?;
+                    Ok(record)
+                }
+            }),
+            ("update", quote! {
+                fn update<'a>(&self, id: #key_name, mut form: #form_name, version: Option<i64>) ->
+                        Result<Option<#model_name>, ResourceStorageError> {
+
+                    #update
+                }
+            }),
+            ("patch", quote! {
+                fn patch<'a>(&self, id: #key_name, mut patch: #patch_name, version: Option<i64>) ->
+                        Result<Option<#model_name>, ResourceStorageError> {
+
+                    if patch.is_empty() {
+                        return <Self as #get_storage>::get(self, id);
+                    }
+                    #patch
+                }
+            }),
+            ("json_patch", quote! {
+                fn json_patch<'a>(&self, id: #key_name,
+                                  operations: Vec<spoilers::patch::Operation>,
+                                  version: Option<i64>) ->
+                        Result<Option<#model_name>, ResourceStorageError> {
+
+                    use spoilers::patch::Operation;
+
+                    // Operations on missing paths roll back the whole patch,
+                    // like `spoilers::patch::apply` does.
+                    let patched = self.db.transaction(|| {
+                        #touch_json_patch
+                        for operation in &operations {
+                            let (field, path) = match spoilers::patch::field_path(operation.path()) {
+                                Some(field_path) => field_path,
+                                None => continue,
+                            };
+                            let target = #table_name::table.find(id.clone());
+                            let updated = match field.as_str() {
+                                #(#json_patch_arms)*
+                                _ => 1,
+                            };
+                            if updated == 0 {
+                                let current = #table_name::table.find(id.clone())
+                                    .first::<#model_name>(&*self.db)
+                                    .optional()?;
+                                if current.is_none() {
+                                    return Ok(None);
+                                }
+                                return Err(ResourceStorageError::Unprocessable(
+                                    format!("Invalid path `{}`.", operation.path())
+                                ));
+                            }
+                        }
+                        #table_name::table.find(id.clone())
+                            .first::<#model_name>(&*self.db)
+                            .optional()
+                            .map_err(ResourceStorageError::from)
+                    })?;
+                    Ok(patched)
+                }
+            }),
+            ("delete", quote! {
+                fn delete<'a>(&self, id: #key_name, version: Option<i64>) ->
+                        Result<Option<#model_name>, ResourceStorageError> {
+
+                    let target = #table_name::table.find(id).filter(#version_condition);
+                    let deleted = diesel::delete(target)
+                        .get_result::<#model_name>(&*self.db)
+                        .optional()?;
+                    Ok(deleted)
+                }
+            }),
+            ("delete_many", quote! {
+                fn delete_many<'a>(&self, filters: #filter_name) ->
+                        Result<Vec<#model_name>, ResourceStorageError> {
+
+                    // Boxed queries can't be deleted, so matching keys are selected first.
+                    let mut query = #table_name::table.select(#key_columns).into_boxed();
+                    #filter_clauses
+                    let keys = query.load::<#key_type>(&*self.db)?;
+
+                    let deleted = #delete_keys;
+                    Ok(deleted)
+                }
+            }),
+        ]);
+
+        quote! {
+            #(#storage_impls)*
         }
     }
 
@@ -195,9 +1294,228 @@ impl MetaResourceConfig {
         let struct_name = self.struct_name();
         let form_name = self.form_name();
         let model_name = self.model_name();
+        let patch_name = self.patch_name();
         let filter_name = self.filter_name();
         let table_name = self.table_name();
         let queue_name = self.table_name().as_ref().to_owned();
+        let get_storage = self.storage_trait("get");
+        let update_storage = self.storage_trait("update");
+        let filter_clauses = self.filter_clauses(false);
+        let select_clause = self.select_clause();
+        let touch_form = self.touch_form(true);
+        let touch_patch = self.touch_patch(true);
+        let timestamp = self.impl_timestamp(true);
+        let latest_version = self.latest_version();
+        let queued_version = self.queued_version();
+        let version_condition = self.version_condition();
+        let key_name = self.key_name();
+        let key_type = self.key_type();
+        let key_columns = self.key_columns();
+        let delete_keys = self.delete_keys();
+        let serial_name = self.serial_name();
+        let find_queued = self.queued_lookup(quote!{
+            spoilers::queue::find(&*self.queue, #queue_name, &#struct_name::key_json(&id))
+        });
+        let replace_queued = self.queued_lookup(quote!{
+            spoilers::queue::replace(&*self.queue, #queue_name, &#struct_name::key_json(&id),
+                                     serde_json::to_value(&form)?)
+        });
+        let modify_queued = self.queued_lookup(quote!{
+            spoilers::queue::modify(&*self.queue, #queue_name, &#struct_name::key_json(&id),
+                                    |record| spoilers::patch::assign(record, &changes))
+        });
+        let remove_queued = self.queued_lookup(quote!{
+            spoilers::queue::remove(&*self.queue, #queue_name, &#struct_name::key_json(&id))
+        });
+
+        let storage_impls = self.storage_impls(vec![
+            ("create", quote! {
+                fn create<'a>(&self, form: #form_name) ->
+                        Result<#model_name, ResourceStorageError> {
+                    #latest_version
+                    let model_json = spoilers::queue::push(
+                        &*self.queue, #queue_name, serde_json::to_value(&form)?, #serial_name,
+                        #queued_version
+                    )?;
+                    let result: #model_name = serde_json::from_value(model_json)?;
+                    Ok(result)
+                }
+            }),
+            ("bulk_create", quote! {
+                fn bulk_create<'a>(&self, form: Vec<#form_name>) ->
+                        Result<Vec<#model_name>, ResourceStorageError> {
+                    #latest_version
+                    let mut created: Vec<#model_name> = vec![];
+                    for form in form {
+                        let model_json = spoilers::queue::push(
+                            &*self.queue, #queue_name, serde_json::to_value(&form)?, #serial_name,
+                            #queued_version
+                        )?;
+                        created.push(serde_json::from_value(model_json)?);
+                    }
+                    Ok(created)
+                }
+            }),
+            ("list", quote! {
+                fn list<'a>(&self, filters: #filter_name, options: &spoilers::query::ListOptions) ->
+                        Result<Vec<#model_name>, ResourceStorageError> {
+
+                    let mut query = #table_name::table.select(#select_clause).into_boxed();
+                    #filter_clauses
+                    if let Some(after) = options.after_sql() {
+                        query = query.filter(diesel::expression::dsl::sql::<diesel::types::Bool>(&after));
+                    }
+                    let mut db_results = query
+                        .order(diesel::expression::dsl::sql::<diesel::types::Bool>(&options.order_by()))
+                        .limit(options.limit)
+                        .load::<#model_name>(&*self.db)?;
+                    let cached = spoilers::queue::records(&*self.queue, #queue_name)?;
+                    for model_json in cached {
+                        if !options.is_after(&model_json) {
+                            continue;
+                        }
+                        let record: #model_name = serde_json::from_value(model_json)?;
+                        if filters.matches(&record) {
+                            db_results.push(record);
+                        }
+                    }
+                    db_results.sort_by(|a, b| a.compare(b, &options.sort));
+                    db_results.truncate(options.limit as usize);
+                    Ok(db_results)
+                }
+            }),
+            ("count", quote! {
+                fn count<'a>(&self, filters: #filter_name) ->
+                        Result<i64, ResourceStorageError> {
+
+                    let mut query = #table_name::table
+                        .select(diesel::expression::dsl::count_star())
+                        .into_boxed();
+                    #filter_clauses
+                    let count = query.first::<i64>(&*self.db)?;
+                    // Records waiting in the queue are not synced yet.
+                    let queued = spoilers::queue::records(&*self.queue, #queue_name)?;
+                    let pending = queued.into_iter().filter_map(|model_json| {
+                        serde_json::from_value::<#model_name>(model_json).ok()
+                    }).filter(|record| filters.matches(record)).count();
+                    Ok(count + pending as i64)
+                }
+            }),
+            ("timestamp", timestamp),
+            ("get", quote! {
+                fn get<'a>(&self, id: #key_name) ->
+                        Result<Option<#model_name>, ResourceStorageError> {
+
+                    #find_queued
+
+                    let record = #table_name::table.find(id)
+                        .first::<#model_name>(&*self.db)
+                        .optional()?;
+                    Ok(record)
+                }
+            }),
+            ("update", quote! {
+                fn update<'a>(&self, id: #key_name, mut form: #form_name, version: Option<i64>) ->
+                        Result<Option<#model_name>, ResourceStorageError> {
+
+                    #latest_version
+                    #touch_form
+                    #replace_queued
+
+                    let target = #table_name::table.find(id).filter(#version_condition);
+                    let updated = diesel::update(target)
+                        .set(&form)
+                        .get_result::<#model_name>(&*self.db)
+                        .optional()?;
+                    Ok(updated)
+                }
+            }),
+            ("patch", quote! {
+                fn patch<'a>(&self, id: #key_name, mut patch: #patch_name, version: Option<i64>) ->
+                        Result<Option<#model_name>, ResourceStorageError> {
+
+                    if patch.is_empty() {
+                        return <Self as #get_storage>::get(self, id);
+                    }
+                    #latest_version
+                    #touch_patch
+
+                    // Nested objects were already merged against the record.
+                    let changes = serde_json::to_value(&patch)?;
+                    #modify_queued
+
+                    let target = #table_name::table.find(id).filter(#version_condition);
+                    let patched = diesel::update(target)
+                        .set(&patch)
+                        .get_result::<#model_name>(&*self.db)
+                        .optional()?;
+                    Ok(patched)
+                }
+            }),
+            ("json_patch", quote! {
+                fn json_patch<'a>(&self, id: #key_name,
+                                  operations: Vec<spoilers::patch::Operation>,
+                                  version: Option<i64>) ->
+                        Result<Option<#model_name>, ResourceStorageError> {
+
+                    let current = <Self as #get_storage>::get(self, id.clone())?;
+                    let mut document = match current {
+                        Some(current) => serde_json::to_value(&current)?,
+                        None => return Ok(None),
+                    };
+                    match spoilers::patch::apply(&mut document, &operations) {
+                        Ok(()) => {},
+                        Err(spoilers::patch::PatchError::TestFailed(path)) => {
+                            return Err(ResourceStorageError::Conflict(
+                                format!("Test failed at `{}`.", path)
+                            ));
+                        },
+                        Err(spoilers::patch::PatchError::InvalidPath(path)) => {
+                            return Err(ResourceStorageError::Unprocessable(
+                                format!("Invalid path `{}`.", path)
+                            ));
+                        },
+                    }
+                    let form: #form_name = spoilers::queue::form(document, #serial_name)
+                        .map_err(|err| ResourceStorageError::Unprocessable(err.to_string()))?;
+                    <Self as #update_storage>::update(self, id, form, version)
+                }
+            }),
+            ("delete", quote! {
+                fn delete<'a>(&self, id: #key_name, version: Option<i64>) ->
+                        Result<Option<#model_name>, ResourceStorageError> {
+
+                    #remove_queued
+
+                    let target = #table_name::table.find(id).filter(#version_condition);
+                    let deleted = diesel::delete(target)
+                        .get_result::<#model_name>(&*self.db)
+                        .optional()?;
+                    Ok(deleted)
+                }
+            }),
+            ("delete_many", quote! {
+                fn delete_many<'a>(&self, filters: #filter_name) ->
+                        Result<Vec<#model_name>, ResourceStorageError> {
+
+                    // Boxed queries can't be deleted, so matching keys are selected first.
+                    let mut query = #table_name::table.select(#key_columns).into_boxed();
+                    #filter_clauses
+                    let keys = query.load::<#key_type>(&*self.db)?;
+
+                    let mut deleted = #delete_keys;
+                    let queued = spoilers::queue::remove_matching(&*self.queue, #queue_name, |record| {
+                        serde_json::from_value::<#model_name>(record.clone())
+                            .map(|record| filters.matches(&record))
+                            .unwrap_or(false)
+                    })?;
+                    for model_json in queued {
+                        deleted.push(serde_json::from_value(model_json)?);
+                    }
+                    Ok(deleted)
+                }
+            }),
+        ]);
 
         quote! {
             impl #struct_name {
@@ -220,69 +1538,104 @@ impl MetaResourceConfig {
 
                     let th = thread::spawn(move || {
                         loop {
-                            let cached: Vec<String> = context.queue.lrange(#queue_name, 0, -1)
-                                                                   .unwrap_or_default();
-
-
-                            let cache_results: Vec<#form_name> = cached.iter().map(|s| {
-                                serde_json::from_str(s.as_ref()).unwrap()
-                            }).collect();
-
-                            if cache_results.len() > 0 {
-                                context.bulk_create(cache_results).unwrap();
-                                let _: i32 = context.queue.del(#queue_name).unwrap();
+                            // Failed syncs leave the queue as it is, to be retried.
+                            if let Err(err) = #struct_name::sync_queue(&context) {
+                                spoilers::queue::sync_failed(#queue_name, &err);
                             }
                             thread::sleep(period);
                         }
                     });
                 }
-            }
-
-            impl spoilers::storage::ResourceStorage<#form_name,#model_name,#filter_name>
-                    for Context {
 
-                fn create<'a>(&self, form: #form_name) ->
-                        Result<#model_name, ResourceStorageError> {
+                /// Moves all queued records to the database. Records it rejects
+                /// are set aside, while failures to reach it leave them queued.
+                fn sync_queue(context: &Context) -> Result<(), ResourceStorageError> {
                     use redis::Commands;
-                    let serialized: String = serde_json::to_string(&form).unwrap();
-                    let _: i32 = self.queue.rpush(#queue_name, serialized.clone()).unwrap();
 
-                    let mut model_json: serde_json::Value =
-                        serde_json::from_str(serialized.as_ref()).unwrap();
+                    spoilers::queue::locked(&*context.queue, #queue_name, || {
+                        // Records pushed meanwhile are appended, so only those read here
+                        // are trimmed once synced.
+                        let cached: Vec<String> = context.queue.lrange(#queue_name, 0, -1)?;
 
-                    model_json["id"] = json!(-1);
-                    let result: #model_name = serde_json::from_value(model_json).unwrap();
-                    Ok(result)
-                }
-
-                fn bulk_create<'a>(&self, form: Vec<#form_name>) ->
-                        Result<#model_name, ResourceStorageError> {
-                    let created: #model_name = diesel::insert(&form).into(#table_name::table)
-                        .get_result(&*self.db)
-                        .expect("Error saving new post");
-                    Ok(created)
-                }
+                        let mut rejected: Vec<(usize, String)> = vec![];
+                        let mut positions: Vec<usize> = vec![];
+                        let mut forms: Vec<#form_name> = vec![];
+                        for (position, s) in cached.iter().enumerate() {
+                            let form = serde_json::from_str(s.as_ref())
+                                .and_then(|record| spoilers::queue::form(record, #serial_name));
+                            match form {
+                                Ok(form) => {
+                                    positions.push(position);
+                                    forms.push(form);
+                                },
+                                Err(err) => rejected.push((position, err.to_string())),
+                            }
+                        }
+                        if cached.is_empty() {
+                            return Ok(());
+                        }
 
-                fn list<'a>(&self, _filters: #filter_name) ->
-                        Result<Vec<#model_name>, ResourceStorageError> {
+                        // Provisional ids keep addressing records once stored.
+                        let mut ids: Vec<(i64, i64)> = vec![];
+                        let mut failed = None;
+                        if !forms.is_empty() {
+                            let inserted = diesel::insert(&forms).into(#table_name::table)
+                                .get_results::<#model_name>(&*context.db)
+                                .map_err(ResourceStorageError::from);
+                            match inserted {
+                                Ok(stored) => {
+                                    for (&position, stored) in positions.iter().zip(&stored) {
+                                        ids.extend(spoilers::queue::synced_id(
+                                            &cached[position], stored, #serial_name
+                                        ));
+                                    }
+                                },
+                                Err(ResourceStorageError::Unavailable(reason)) => {
+                                    return Err(ResourceStorageError::Unavailable(reason));
+                                },
+                                Err(_) => {
+                                    // Records are inserted one by one instead, to find
+                                    // those rejected.
+                                    for (&position, form) in positions.iter().zip(&forms) {
+                                        let inserted = diesel::insert(form).into(#table_name::table)
+                                            .get_result::<#model_name>(&*context.db)
+                                            .map_err(ResourceStorageError::from);
+                                        match inserted {
+                                            Ok(stored) => ids.extend(spoilers::queue::synced_id(
+                                                &cached[position], &stored, #serial_name
+                                            )),
+                                            Err(ResourceStorageError::Unavailable(reason)) => {
+                                                let err = ResourceStorageError::Unavailable(reason);
+                                                failed = Some((position, err));
+                                                break;
+                                            },
+                                            Err(err) => {
+                                                rejected.push((position, err.reason().to_owned()));
+                                            },
+                                        }
+                                    }
+                                },
+                            }
+                        }
 
-                    use redis::Commands;
-                    use #table_name::dsl::*;
-                    let mut db_results = #table_name
-                        .limit(1000)
-                        .load::<#model_name>(&*self.db)
-                        .expect("Error loading events");
-                    let cached: Vec<String> = self.queue.lrange(#queue_name, 0, -1).unwrap();
-                    let cache_results: Vec<#model_name> = cached.iter().map(|s| {
-                        let mut model_json: serde_json::Value =
-                            serde_json::from_str(s.as_ref()).unwrap();
-                        model_json["id"] = json!(-1);
-                        serde_json::from_value(model_json).unwrap()
-                    }).collect();
-                    db_results.extend(cache_results);
-                    Ok(db_results)
+                        // Records from the first one the database couldn't be reached for
+                        // are kept queued, to be retried.
+                        let (count, result) = match failed {
+                            Some((position, err)) => (position, Err(err)),
+                            None => (cached.len(), Ok(())),
+                        };
+                        let rejected: Vec<(&str, String)> = rejected.into_iter()
+                            .filter(|&(position, _)| position < count)
+                            .map(|(position, reason)| (cached[position].as_str(), reason))
+                            .collect();
+                        spoilers::queue::synced(&*context.queue, #queue_name, count, &rejected,
+                                                &ids)?;
+                        result
+                    })
                 }
             }
+
+            #(#storage_impls)*
         }
     }
 }
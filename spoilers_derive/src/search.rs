@@ -0,0 +1,92 @@
+use syn;
+use quote;
+
+use resource::MetaResourceConfig;
+use utils::{is_option, type_name};
+
+
+impl MetaResourceConfig {
+
+    /// Fields marked as `#[searchable]`, matched by `?_q=`.
+    pub fn searchable_fields(&self) -> Vec<&syn::Field> {
+        self.fields().iter().filter(|field| {
+            field.attrs.iter().any(|attr| attr.name() == "searchable")
+        }).map(|field| {
+            if type_name(&field.ty) != "String" {
+                panic!("Only text fields can be searchable");
+            }
+            field
+        }).collect()
+    }
+
+    pub fn searchable_names(&self) -> Vec<String> {
+        self.searchable_fields().iter().map(|field| {
+            field.ident.clone().unwrap().as_ref().to_owned()
+        }).collect()
+    }
+
+    /// Generates the `search_index_sql()` helper, giving the GIN index
+    /// statement to be added to migrations.
+    pub fn impl_search(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+        let table_name = self.table_name().as_ref().to_owned();
+        let searchable = self.searchable_names();
+
+        if searchable.is_empty() {
+            return quote!{};
+        }
+        quote! {
+            impl #struct_name {
+                /// Statement creating the index used by `?_q=` full-text search.
+                pub fn search_index_sql() -> String {
+                    spoilers::search::index_sql(#table_name, &[#(#searchable),*])
+                }
+            }
+        }
+    }
+
+    /// Generates the statement narrowing a boxed Diesel query by `?_q=`,
+    /// with full-text search or its `ILIKE` fallback.
+    pub fn search_clause(&self, full_text: bool) -> quote::Tokens {
+        let searchable = self.searchable_names();
+
+        if searchable.is_empty() {
+            return quote!{};
+        }
+        let condition = if full_text {
+            quote!{ spoilers::search::sql(&[#(#searchable),*], q) }
+        } else {
+            quote!{ spoilers::search::fallback_sql(&[#(#searchable),*], q) }
+        };
+        quote! {
+            if let Some(ref q) = filters._search {
+                query = query.filter(
+                    diesel::expression::dsl::sql::<diesel::types::Bool>(&#condition)
+                );
+            }
+        }
+    }
+
+    /// Generates the in-memory check of `?_q=` against a record.
+    pub fn search_check(&self) -> quote::Tokens {
+        let values: Vec<quote::Tokens> = self.searchable_fields().iter().map(|field| {
+            let ident = &field.ident;
+            if is_option(&field.ty) {
+                quote!{ record.#ident.as_ref().map(|value| value.as_str()) }
+            } else {
+                quote!{ Some(record.#ident.as_str()) }
+            }
+        }).collect();
+
+        if values.is_empty() {
+            return quote!{};
+        }
+        quote! {
+            if let Some(ref q) = self._search {
+                if !spoilers::search::matches(&[#(#values),*], q) {
+                    return false;
+                }
+            }
+        }
+    }
+}
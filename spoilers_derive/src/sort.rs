@@ -0,0 +1,88 @@
+use syn;
+use quote;
+
+use resource::MetaResourceConfig;
+use utils::{is_json, is_option, type_name};
+
+
+impl MetaResourceConfig {
+
+    /// Names of the fields collections can be sorted by. JSON documents
+    /// have no meaningful order, so they are left out.
+    pub fn sortable_fields(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.key_column().into_iter().map(|(ident, _)| {
+            ident.as_ref().to_owned()
+        }).collect();
+        names.extend(self.fields().iter().filter(|field| !is_json(&field.ty)).map(|field| {
+            field.ident.clone().unwrap().as_ref().to_owned()
+        }));
+        names
+    }
+
+    /// Names of the sortable fields holding text, which the database sorts
+    /// bytewise like `compare` does.
+    pub fn text_fields(&self) -> Vec<String> {
+        self.fields().iter().filter(|field| type_name(&field.ty) == "String").map(|field| {
+            field.ident.clone().unwrap().as_ref().to_owned()
+        }).collect()
+    }
+
+    /// Generates the in-memory comparison of records by sort keys, matching
+    /// the order given by the database, with the primary key breaking ties.
+    pub fn impl_sort(&self) -> quote::Tokens {
+        let model_name = self.model_name();
+
+        let (key_arm, tie_break) = match self.key_column() {
+            Some((ident, _)) => {
+                let name = ident.as_ref().to_owned();
+                (quote!{ #name => self.#ident.cmp(&other.#ident), },
+                 quote!{ self.#ident.cmp(&other.#ident) })
+            },
+            None => {
+                let idents: Vec<syn::Ident> = self.key_names().into_iter().map(syn::Ident::new)
+                    .collect();
+                let orderings: Vec<quote::Tokens> = idents.iter().map(|ident| {
+                    quote!{ spoilers::query::compare(Some(&self.#ident), Some(&other.#ident)) }
+                }).collect();
+                let (first, rest) = orderings.split_first().unwrap();
+                (quote!{}, quote!{ #first #(.then(#rest))* })
+            },
+        };
+
+        let compare_arms: Vec<quote::Tokens> = self.fields().iter().filter(|field| {
+            !is_json(&field.ty)
+        }).map(|field| {
+            let ident = &field.ident;
+            let name = field.ident.clone().unwrap().as_ref().to_owned();
+            if is_option(&field.ty) {
+                quote!{
+                    #name => spoilers::query::compare(self.#ident.as_ref(), other.#ident.as_ref()),
+                }
+            } else {
+                quote!{
+                    #name => spoilers::query::compare(Some(&self.#ident), Some(&other.#ident)),
+                }
+            }
+        }).collect();
+
+        quote! {
+            impl #model_name {
+                pub fn compare(&self, other: &#model_name, sort: &[spoilers::query::SortKey])
+                        -> std::cmp::Ordering {
+                    for key in sort {
+                        let ordering = match key.field.as_ref() {
+                            #key_arm
+                            #(#compare_arms)*
+                            _ => std::cmp::Ordering::Equal,
+                        };
+                        let ordering = if key.descending { ordering.reverse() } else { ordering };
+                        if ordering != std::cmp::Ordering::Equal {
+                            return ordering;
+                        }
+                    }
+                    #tie_break
+                }
+            }
+        }
+    }
+}
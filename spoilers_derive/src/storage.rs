@@ -2,8 +2,78 @@ use syn;
 use quote;
 
 
+/// Generates the `POST /batch` route, dispatching sub-requests to the
+/// resources registered in `spoilers::batch::BatchRoutes`. Atomic batches run
+/// inside a single database transaction, unless writes aren't `transactional`,
+/// e.g. queued ones, in which case they are refused.
+fn impl_batch_route(transactional: bool) -> quote::Tokens {
+    let atomic_batch = if transactional {
+        quote! {
+            let committed = context.db.transaction::<(), diesel::result::Error, _>(|| {
+                for request in &batch.requests {
+                    let response = routes.dispatch(&context, request);
+                    let failed = !response.is_success();
+                    responses.push(response);
+                    if failed {
+                        return Err(diesel::result::Error::RollbackTransaction);
+                    }
+                }
+                Ok(())
+            });
+
+            // A rolled back batch takes the status of the failed sub-request.
+            let status = match committed {
+                Ok(()) => Status::Ok,
+                Err(_) => responses.last().and_then(|response| {
+                    Status::from_code(response.status)
+                }).unwrap_or(Status::InternalServerError),
+            };
+            Custom(status, rocket_contrib::JsonValue(json!({"data": responses})))
+        }
+    } else {
+        quote! {
+            spoilers::errors::response(
+                Status::BadRequest, spoilers::errors::Errno::InvalidPostedData,
+                "Atomic batches aren't supported, as queued writes can't be rolled back.",
+                json!({"field": "atomic"})
+            )
+        }
+    };
+
+    quote! {
+        impl spoilers::batch::BatchContext for Context {
+            fn batch_routes() -> Vec<rocket::Route> {
+                routes![batch]
+            }
+        }
+
+        #[post("/batch", format = "application/json", data = "<batch>")]
+        pub fn batch(batch: rocket_contrib::Json<spoilers::batch::BatchRequest>,
+                     routes: rocket::State<spoilers::batch::BatchRoutes<Context>>,
+                     context: Context)
+                -> rocket::response::status::Custom<rocket_contrib::JsonValue> {
+            use rocket::http::Status;
+            use rocket::response::status::Custom;
+
+            let batch = batch.into_inner();
+            let mut responses: Vec<spoilers::batch::SubResponse> = vec![];
+
+            if !batch.atomic {
+                for request in &batch.requests {
+                    responses.push(routes.dispatch(&context, request));
+                }
+                return Custom(Status::Ok, rocket_contrib::JsonValue(json!({"data": responses})));
+            }
+
+            #atomic_batch
+        }
+    }
+}
+
+
 pub fn impl_postgre_storage(ast: &syn::DeriveInput) -> quote::Tokens {
     let class_name = &ast.ident;
+    let batch_route = impl_batch_route(true);
 
     quote! {
         type DatabaseConnectionPool = r2d2::Pool<r2d2_diesel::ConnectionManager<diesel::pg::PgConnection>>;
@@ -53,12 +123,15 @@ pub fn impl_postgre_storage(ast: &syn::DeriveInput) -> quote::Tokens {
                 }
             }
         }
+
+        #batch_route
     }
 }
 
 
 pub fn impl_redshift_storage(ast: &syn::DeriveInput) -> quote::Tokens {
     let class_name = &ast.ident;
+    let batch_route = impl_batch_route(false);
 
     quote! {
         type DatabaseConnectionPool = r2d2::Pool<r2d2_diesel::ConnectionManager<diesel::pg::PgConnection>>;
@@ -133,5 +206,7 @@ pub fn impl_redshift_storage(ast: &syn::DeriveInput) -> quote::Tokens {
                 }
             }
         }
+
+        #batch_route
     }
 }
@@ -0,0 +1,213 @@
+use syn;
+use quote;
+
+use resource::MetaResourceConfig;
+use utils::{is_option, type_name};
+
+
+impl MetaResourceConfig {
+
+    /// Field marked as `#[last_modified]`, holding the epoch milliseconds of
+    /// the last change of a record.
+    pub fn last_modified_field(&self) -> Option<&syn::Field> {
+        let mut fields = self.fields().iter().filter(|field| {
+            field.attrs.iter().any(|attr| attr.name() == "last_modified")
+        });
+        let field = fields.next();
+        if fields.next().is_some() {
+            panic!("Only one field can be last_modified");
+        }
+        if let Some(field) = field {
+            if is_option(&field.ty) || type_name(&field.ty) != "i64" {
+                panic!("last_modified fields must be i64");
+            }
+        }
+        field
+    }
+
+    pub fn last_modified_name(&self) -> Option<String> {
+        self.last_modified_field().map(|field| field.ident.clone().unwrap().as_ref().to_owned())
+    }
+
+    /// Generates the statement binding the latest stored version of the
+    /// collection to `latest`, the new versions of writes being later than it.
+    pub fn latest_version(&self) -> quote::Tokens {
+        let table_name = self.table_name();
+
+        match self.last_modified_field() {
+            Some(field) => {
+                let ident = &field.ident;
+                quote!{
+                    let latest = #table_name::table
+                        .select(diesel::expression::dsl::max(#table_name::#ident))
+                        .first::<Option<i64>>(&*self.db)?;
+                }
+            },
+            None => quote!{},
+        }
+    }
+
+    /// Generates the statements locking the versions of the collection until
+    /// the transaction they run in commits, then binding `latest`. Writes are
+    /// serialized this way, so versions are given in commit order and
+    /// `_since` doesn't miss any.
+    pub fn lock_versions(&self) -> quote::Tokens {
+        let lock = format!("SELECT pg_advisory_xact_lock(hashtext('{}'))",
+                           self.table_name().as_ref());
+        let latest_version = self.latest_version();
+
+        match self.last_modified_field() {
+            Some(_) => quote!{
+                diesel::connection::SimpleConnection::batch_execute(&*self.db, #lock)?;
+                #latest_version
+            },
+            None => quote!{},
+        }
+    }
+
+    /// Generates a `write` giving new versions, run in a transaction holding
+    /// the lock of `lock_versions`.
+    pub fn versioned(&self, write: quote::Tokens) -> quote::Tokens {
+        let lock_versions = self.lock_versions();
+
+        match self.last_modified_field() {
+            Some(_) => quote!{
+                self.db.transaction(|| {
+                    #lock_versions
+                    #write
+                })
+            },
+            None => write,
+        }
+    }
+
+    /// Generates the expression of the next version given the `latest` one.
+    /// Versions of queued collections are given by the queue, so writers
+    /// don't share them.
+    fn next_version(&self, queued: bool) -> quote::Tokens {
+        let queue_name = self.table_name().as_ref().to_owned();
+        if queued {
+            quote!{ spoilers::queue::next_version(&*self.queue, #queue_name, latest)? }
+        } else {
+            quote!{ spoilers::storage::next_version(latest) }
+        }
+    }
+
+    /// Generates the statement setting the version of a form, bound to
+    /// `form`, given the `latest` one.
+    pub fn touch_form(&self, queued: bool) -> quote::Tokens {
+        let next_version = self.next_version(queued);
+        match self.last_modified_field() {
+            Some(field) => {
+                let ident = &field.ident;
+                quote!{ form.#ident = #next_version; }
+            },
+            None => quote!{},
+        }
+    }
+
+    /// Generates the statement setting the version of a patch, bound to
+    /// `patch`, given the `latest` one.
+    pub fn touch_patch(&self, queued: bool) -> quote::Tokens {
+        let next_version = self.next_version(queued);
+        match self.last_modified_field() {
+            Some(field) => {
+                let ident = &field.ident;
+                quote!{ patch.#ident = Some(#next_version); }
+            },
+            None => quote!{},
+        }
+    }
+
+    /// Generates the version argument of `spoilers::queue::push`, which gives
+    /// pushed records their version given the `latest` one.
+    pub fn queued_version(&self) -> quote::Tokens {
+        match self.last_modified_name() {
+            Some(name) => quote!{ Some((#name, latest)) },
+            None => quote!{ None },
+        }
+    }
+
+    /// Generates the parsing of `_since` and `_before` into conditions on the
+    /// modification time, within the filters query string loop.
+    pub fn since_params(&self) -> quote::Tokens {
+        match self.last_modified_field() {
+            Some(field) => {
+                let ident = &field.ident;
+                quote!{
+                    if name == "_since" {
+                        filter.#ident.push(spoilers::query::Condition::Gt(
+                            spoilers::query::parse_value(name, value)?
+                        ));
+                        continue;
+                    }
+                    if name == "_before" {
+                        filter.#ident.push(spoilers::query::Condition::Lt(
+                            spoilers::query::parse_value(name, value)?
+                        ));
+                        continue;
+                    }
+                }
+            },
+            None => quote!{},
+        }
+    }
+
+    /// Generates the `timestamp` storage method. Counting records along the
+    /// latest version makes deletes change it too.
+    pub fn impl_timestamp(&self, queued: bool) -> quote::Tokens {
+        let table_name = self.table_name();
+        let queue_name = self.table_name().as_ref().to_owned();
+        let latest_version = self.latest_version();
+
+        let body = match self.last_modified_field() {
+            Some(_) => {
+                let queued_latest = if queued {
+                    quote!{
+                        let queued = spoilers::queue::latest_version(&*self.queue, #queue_name)?;
+                        let latest = latest.into_iter().chain(queued).max();
+                        let count = count + spoilers::queue::len(&*self.queue, #queue_name)?;
+                    }
+                } else {
+                    quote!{}
+                };
+                quote!{
+                    #latest_version
+                    let count = #table_name::table
+                        .select(diesel::expression::dsl::count_star())
+                        .first::<i64>(&*self.db)?;
+                    #queued_latest
+                    Ok(latest.map(|latest| (latest, count)))
+                }
+            },
+            None => quote!{ Ok(None) },
+        };
+
+        quote! {
+            fn timestamp<'a>(&self) -> Result<Option<(i64, i64)>, ResourceStorageError> {
+                #body
+            }
+        }
+    }
+
+    /// Generates the compare-and-swap condition of writes, given the
+    /// expected `version`.
+    pub fn version_condition(&self) -> quote::Tokens {
+        let condition = match self.last_modified_name() {
+            Some(name) => quote!{ spoilers::storage::version_sql(#name, version) },
+            None => quote!{ spoilers::storage::version_sql("id", None) },
+        };
+        quote! {
+            diesel::expression::dsl::sql::<diesel::types::Bool>(&#condition)
+        }
+    }
+
+    /// Generates the sort used when none is requested: by modification time
+    /// if it is tracked.
+    pub fn default_sort(&self) -> quote::Tokens {
+        match self.last_modified_name() {
+            Some(name) => quote!{ Some(#name) },
+            None => quote!{ None },
+        }
+    }
+}
@@ -14,3 +14,54 @@ pub fn parse_derive_input(input: TokenStream) -> syn::DeriveInput {
 pub fn parse_derive_attibutes<'a>(ast: syn::DeriveInput) -> HashMap<String, syn::MetaItem> {
     ast.attrs.iter().map(|x| (x.name().to_owned(), x.value.clone())).collect()
 }
+
+
+/// Util to check if a field type is an `Option`, i.e. a nullable column.
+pub fn is_option(ty: &syn::Ty) -> bool {
+    match *ty {
+        syn::Ty::Path(_, ref path) => {
+            path.segments.last().map_or(false, |segment| segment.ident.as_ref() == "Option")
+        },
+        _ => false
+    }
+}
+
+
+/// Util to get the type wrapped by an `Option`, or the type itself.
+pub fn inner_type(ty: &syn::Ty) -> &syn::Ty {
+    if let syn::Ty::Path(_, ref path) = *ty {
+        if let Some(segment) = path.segments.last() {
+            if segment.ident.as_ref() == "Option" {
+                if let syn::PathParameters::AngleBracketed(ref data) = segment.parameters {
+                    if let Some(inner) = data.types.first() {
+                        return inner;
+                    }
+                }
+            }
+        }
+    }
+    ty
+}
+
+
+/// Util to check if a field holds a JSON document, i.e. a JSONB column.
+pub fn is_json(ty: &syn::Ty) -> bool {
+    match *inner_type(ty) {
+        syn::Ty::Path(_, ref path) => {
+            path.segments.last().map_or(false, |segment| segment.ident.as_ref() == "Value")
+        },
+        _ => false
+    }
+}
+
+
+/// Util to get the name of a field type, without `Option` and module path,
+/// e.g. `NaiveDateTime` for `Option<chrono::NaiveDateTime>`.
+pub fn type_name(ty: &syn::Ty) -> String {
+    match *inner_type(ty) {
+        syn::Ty::Path(_, ref path) => {
+            path.segments.last().map_or(String::new(), |segment| segment.ident.as_ref().to_owned())
+        },
+        _ => String::new()
+    }
+}
@@ -0,0 +1,207 @@
+use syn;
+use quote;
+use spoilers;
+
+use resource::MetaResourceConfig;
+use utils::is_option;
+
+
+/// Number given to a `length` or `range` bound, e.g. `max = 255`.
+fn bound(name: &str, literal: &syn::Lit) -> f64 {
+    let value = match *literal {
+        syn::Lit::Int(value, _) => Ok(value as f64),
+        syn::Lit::Float(ref value, _) | syn::Lit::Str(ref value, _) => value.parse::<f64>(),
+        _ => panic!("validate bound `{}` must be a number", name),
+    };
+    value.expect(&format!("validate bound `{}` must be a number", name))
+}
+
+
+/// Parses the `min` and `max` bounds of `length(...)` and `range(...)`.
+fn bounds(check: &str, items: &[syn::NestedMetaItem]) -> (Option<f64>, Option<f64>) {
+    let (mut min, mut max) = (None, None);
+    for item in items {
+        match *item {
+            syn::NestedMetaItem::MetaItem(syn::MetaItem::NameValue(ref name, ref literal)) => {
+                match name.as_ref() {
+                    "min" => min = Some(bound("min", literal)),
+                    "max" => max = Some(bound("max", literal)),
+                    _ => panic!("Unknown bound `{}` in validate({})", name, check),
+                }
+            },
+            _ => panic!("validate({}) takes min and max bounds", check),
+        }
+    }
+    (min, max)
+}
+
+
+fn float_tokens(value: Option<f64>) -> quote::Tokens {
+    match value {
+        Some(value) => {
+            let literal = syn::Ident::new(format!("{:?}", value));
+            quote!{ Some(#literal) }
+        },
+        None => quote!{ None },
+    }
+}
+
+
+fn usize_tokens(value: Option<f64>) -> quote::Tokens {
+    match value {
+        Some(value) => {
+            let value = value as usize;
+            quote!{ Some(#value) }
+        },
+        None => quote!{ None },
+    }
+}
+
+
+/// Generates the checks of a field given by `#[validate(...)]` attributes,
+/// on a `value` reference to the field value.
+fn field_checks(field: &syn::Field) -> Vec<quote::Tokens> {
+    let name = field.ident.clone().unwrap().as_ref().to_owned();
+    let mut checks = vec![];
+
+    for attr in field.attrs.iter().filter(|attr| attr.name() == "validate") {
+        let items = match attr.value {
+            syn::MetaItem::List(_, ref items) => items,
+            _ => panic!("validate attributes must be lists, e.g. #[validate(length(max = 255))]"),
+        };
+        for item in items {
+            let check = match *item {
+                syn::NestedMetaItem::MetaItem(syn::MetaItem::List(ref check, ref args))
+                        if check.as_ref() == "length" => {
+                    let (min, max) = bounds("length", args);
+                    let (min, max) = (usize_tokens(min), usize_tokens(max));
+                    quote!{ spoilers::validate::length(value, #min, #max) }
+                },
+                syn::NestedMetaItem::MetaItem(syn::MetaItem::List(ref check, ref args))
+                        if check.as_ref() == "range" => {
+                    let (min, max) = bounds("range", args);
+                    let (min, max) = (float_tokens(min), float_tokens(max));
+                    quote!{ spoilers::validate::range(*value as f64, #min, #max) }
+                },
+                syn::NestedMetaItem::MetaItem(syn::MetaItem::List(ref check, ref args))
+                        if check.as_ref() == "one_of" => {
+                    let choices: Vec<String> = args.iter().map(|arg| match *arg {
+                        syn::NestedMetaItem::Literal(syn::Lit::Str(ref choice, _)) => {
+                            choice.clone()
+                        },
+                        syn::NestedMetaItem::Literal(syn::Lit::Int(choice, _)) => {
+                            choice.to_string()
+                        },
+                        _ => panic!("validate(one_of(...)) takes string or integer literals"),
+                    }).collect();
+                    quote!{ spoilers::validate::one_of(value, &[#(#choices),*]) }
+                },
+                syn::NestedMetaItem::MetaItem(
+                    syn::MetaItem::NameValue(ref check, syn::Lit::Str(ref pattern, _))
+                ) if check.as_ref() == "regex" => {
+                    if let Err(err) = spoilers::regex::Regex::new(pattern) {
+                        panic!("Invalid validate(regex) pattern `{}`: {}", pattern, err);
+                    }
+                    quote!{ spoilers::validate::regex(value, #pattern) }
+                },
+                syn::NestedMetaItem::MetaItem(
+                    syn::MetaItem::NameValue(ref check, syn::Lit::Str(ref function, _))
+                ) if check.as_ref() == "custom" => {
+                    let function = syn::parse_path(function)
+                        .expect("validate(custom) must name a function");
+                    quote!{ #function(value) }
+                },
+                _ => panic!("Unknown validation of field `{}`", name),
+            };
+            checks.push(quote!{ errors.check(#name, #check); });
+        }
+    }
+    checks
+}
+
+
+impl MetaResourceConfig {
+
+    /// Generates the `validate` methods of forms and patches, enforcing the
+    /// `#[validate(...)]` attributes of fields. Checks are skipped for null
+    /// and missing values.
+    ///
+    /// Custom checks name a function taking a reference to the value:
+    /// `#[validate(custom = "check_title")]` calls
+    /// `fn check_title(value: &String) -> Result<(), String>`.
+    pub fn impl_validation(&self) -> quote::Tokens {
+        let form_name = self.form_name();
+        let patch_name = self.patch_name();
+
+        if self.validated_names().is_empty() {
+            return quote! {
+                impl #form_name {
+                    pub fn validate(&self) -> Result<(), spoilers::validate::ValidationErrors> {
+                        Ok(())
+                    }
+                }
+
+                impl #patch_name {
+                    pub fn validate(&self) -> Result<(), spoilers::validate::ValidationErrors> {
+                        Ok(())
+                    }
+                }
+            };
+        }
+
+        let mut form_checks: Vec<quote::Tokens> = vec![];
+        let mut patch_checks: Vec<quote::Tokens> = vec![];
+        for field in self.fields() {
+            let checks = field_checks(field);
+            if checks.is_empty() {
+                continue;
+            }
+            let ident = &field.ident;
+            let checks = quote!{ #(#checks)* };
+            // Patches leave out the fields of composite keys.
+            let key_field = self.is_key_field(ident.as_ref().unwrap().as_ref());
+            if is_option(&field.ty) {
+                form_checks.push(quote!{
+                    if let Some(ref value) = self.#ident { #checks }
+                });
+                patch_checks.push(quote!{
+                    if let Some(Some(ref value)) = self.#ident { #checks }
+                });
+            } else {
+                form_checks.push(quote!{
+                    { let value = &self.#ident; #checks }
+                });
+                if !key_field {
+                    patch_checks.push(quote!{
+                        if let Some(ref value) = self.#ident { #checks }
+                    });
+                }
+            }
+        }
+
+        quote! {
+            impl #form_name {
+                pub fn validate(&self) -> Result<(), spoilers::validate::ValidationErrors> {
+                    let mut errors = spoilers::validate::ValidationErrors::new();
+                    #(#form_checks)*
+                    errors.into_result()
+                }
+            }
+
+            impl #patch_name {
+                pub fn validate(&self) -> Result<(), spoilers::validate::ValidationErrors> {
+                    let mut errors = spoilers::validate::ValidationErrors::new();
+                    #(#patch_checks)*
+                    errors.into_result()
+                }
+            }
+        }
+    }
+
+    /// Names of the fields with `#[validate(...)]` attributes.
+    pub fn validated_names(&self) -> Vec<String> {
+        self.fields().iter().filter(|field| {
+            field.attrs.iter().any(|attr| attr.name() == "validate")
+        }).map(|field| field.ident.clone().unwrap().as_ref().to_owned()).collect()
+    }
+}
@@ -0,0 +1,146 @@
+use syn;
+use quote;
+
+use keys::PrimaryKey;
+use resource::MetaResourceConfig;
+use utils::parse_derive_attibutes;
+
+
+/// Verbs a resource can serve, all of them by default.
+const VERBS: [&'static str; 7] = ["list", "create", "get", "update", "patch", "delete",
+                                  "delete_all"];
+
+
+impl MetaResourceConfig {
+
+    /// Verbs served by the resource, set with
+    /// `#[resource(verbs="list,create,get")]`.
+    pub fn verbs(&self) -> Vec<String> {
+        let attr_items = parse_derive_attibutes(self.ast.clone());
+        let items = match attr_items.get("resource") {
+            Some(&syn::MetaItem::List(_, ref items)) => items.clone(),
+            Some(_) => panic!("resource must be a list, e.g. #[resource(verbs=\"list\")]"),
+            None => vec![],
+        };
+        let verbs = items.iter().filter_map(|item| match *item {
+            syn::NestedMetaItem::MetaItem(
+                syn::MetaItem::NameValue(ref name, syn::Lit::Str(ref verbs, _))
+            ) if name.as_ref() == "verbs" => Some(verbs.clone()),
+            _ => None,
+        }).next();
+
+        match verbs {
+            Some(verbs) => verbs.split(',').map(|verb| verb.trim()).filter(|verb| {
+                !verb.is_empty()
+            }).map(|verb| {
+                if !VERBS.contains(&verb) {
+                    panic!("Unknown verb `{}`, expected one of: {}", verb, VERBS.join(", "));
+                }
+                verb.to_owned()
+            }).collect(),
+            None => VERBS.iter().map(|verb| verb.to_string()).collect(),
+        }
+    }
+
+    pub fn has_verb(&self, verb: &str) -> bool {
+        self.verbs().iter().any(|served| served == verb)
+    }
+
+    /// Names of the generated routes, to be mounted at the endpoint.
+    pub fn route_names(&self) -> Vec<syn::Ident> {
+        let verbs = self.verbs();
+        let mut methods = vec![];
+        for verb in &verbs {
+            match verb.as_str() {
+                "list" => methods.extend(&["get", "count"]),
+                "create" => methods.push("create"),
+                "get" => methods.push("record_get"),
+                "update" => methods.push("update"),
+                "patch" => methods.extend(&["patch", "json_patch"]),
+                "delete" => methods.push("delete"),
+                "delete_all" => methods.push("collection_delete"),
+                _ => {},
+            }
+        }
+        methods.iter().map(|method| self.method_name(method)).collect()
+    }
+
+    /// Whether the storage backend must implement a method of the
+    /// `spoilers::storage` traits, as the handlers of the served verbs call it.
+    /// Writes read the record to check their preconditions.
+    pub fn needs_storage(&self, method: &str) -> bool {
+        self.verbs().iter().any(|verb| {
+            let methods: &[&str] = match verb.as_str() {
+                "list" => &["list", "count", "timestamp"],
+                // Preconditions of creates only read records at client chosen keys.
+                "create" => if self.assigns_key() {
+                    &["create", "bulk_create"]
+                } else {
+                    &["create", "bulk_create", "get"]
+                },
+                "get" => &["get", "list"],
+                // `If-None-Match: *` creates records at client chosen keys.
+                "update" => match self.primary_key() {
+                    PrimaryKey::Serial { .. } => &["get", "update"],
+                    _ => &["get", "update", "create"],
+                },
+                "patch" => &["get", "patch", "json_patch", "update"],
+                "delete" => &["get", "delete"],
+                "delete_all" => &["delete_many"],
+                _ => &[],
+            };
+            methods.contains(&method)
+        })
+    }
+
+    /// Implements the storage traits needed by the served verbs for the
+    /// request context, with the generated storage methods given by name.
+    pub fn storage_impls(&self, methods: Vec<(&str, quote::Tokens)>) -> Vec<quote::Tokens> {
+        let mut capabilities: Vec<(&str, Vec<quote::Tokens>)> = vec![];
+        for (name, method) in methods {
+            if !self.needs_storage(name) {
+                continue;
+            }
+            let capability = match name {
+                "bulk_create" => "create",
+                "count" | "timestamp" => "list",
+                "json_patch" => "patch",
+                _ => name,
+            };
+            match capabilities.iter().position(|&(known, _)| known == capability) {
+                Some(index) => capabilities[index].1.push(method),
+                None => capabilities.push((capability, vec![method])),
+            }
+        }
+        capabilities.into_iter().map(|(capability, methods)| {
+            let storage_trait = self.storage_trait(capability);
+            quote! {
+                impl #storage_trait for Context {
+                    #(#methods)*
+                }
+            }
+        }).collect()
+    }
+
+    /// Generates the `spoilers::models` capability traits of the served verbs.
+    pub fn impl_capabilities(&self) -> quote::Tokens {
+        let struct_name = self.struct_name();
+
+        let capabilities: Vec<quote::Tokens> = self.verbs().iter().map(|verb| {
+            let capability = syn::Ident::new(match verb.as_str() {
+                "list" => "CollectionGet",
+                "create" => "CollectionCreate",
+                "get" => "RecordGet",
+                "update" => "RecordUpdate",
+                "patch" => "RecordPatch",
+                "delete" => "RecordDelete",
+                _ => "CollectionDelete",
+            });
+            quote!{ impl spoilers::models::#capability for #struct_name {} }
+        }).collect();
+
+        quote! {
+            #(#capabilities)*
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use rocket::Route;
+use rocket::http::Status;
+use rocket::response::Failure;
+use rocket::response::status::Custom;
+use rocket_contrib::JsonValue;
+use serde_json;
+
+use errors::{self, Errno};
+use headers::{Preconditions, WithHeaders};
+use query::Query;
+
+
+/// Body of a `POST /batch` request.
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    /// Runs all sub-requests in a single transaction, stopping at the first
+    /// failure and rolling back the previous ones. Refused by Redshift storage,
+    /// whose queued writes can't be rolled back.
+    #[serde(default)]
+    pub atomic: bool,
+    pub requests: Vec<SubRequest>,
+}
+
+
+/// A single operation of a batch, e.g. `{"method": "PUT", "path": "/warning/1"}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub body: Option<serde_json::Value>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+
+impl SubRequest {
+    /// Splits the path from its query string parameters.
+    pub fn split_query(&self) -> (&str, Query) {
+        match self.path.find('?') {
+            Some(index) => (&self.path[..index], Query::parse(&self.path[index + 1..])),
+            None => (self.path.as_ref(), Query::parse("")),
+        }
+    }
+
+    /// Gets a header of the sub-request, ignoring case like HTTP does.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|&(key, _)| key.to_lowercase() == name.to_lowercase())
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn preconditions(&self) -> Preconditions {
+        Preconditions {
+            if_match: self.header("If-Match").map(|value| value.to_owned()),
+            if_none_match: self.header("If-None-Match").map(|value| value.to_owned()),
+        }
+    }
+
+    /// Gets the sub-request with its path relative to a resource endpoint.
+    fn relative_to(&self, endpoint: &str) -> Option<SubRequest> {
+        let endpoint = endpoint.trim_right_matches('/');
+        if !self.path.starts_with(endpoint) {
+            return None;
+        }
+        let path = &self.path[endpoint.len()..];
+        if !path.is_empty() && !path.starts_with('/') && !path.starts_with('?') {
+            return None;
+        }
+        Some(SubRequest {
+            method: self.method.clone(),
+            path: path.to_owned(),
+            body: self.body.clone(),
+            headers: self.headers.clone(),
+        })
+    }
+}
+
+
+/// Result of a single operation of a batch.
+#[derive(Debug, Serialize)]
+pub struct SubResponse {
+    pub status: u16,
+    pub body: serde_json::Value,
+    #[serde(skip_serializing_if="HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+}
+
+
+impl SubResponse {
+    pub fn error(status: Status) -> SubResponse {
+        SubResponse {
+            status: status.code,
+            body: errors::body(status, Errno::from_status(status), status.reason, json!({})),
+            headers: HashMap::new(),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status < 400
+    }
+}
+
+
+impl From<JsonValue> for SubResponse {
+    fn from(response: JsonValue) -> SubResponse {
+        SubResponse { status: Status::Ok.code, body: response.0, headers: HashMap::new() }
+    }
+}
+
+
+impl From<()> for SubResponse {
+    fn from(_: ()) -> SubResponse {
+        SubResponse {
+            status: Status::Ok.code,
+            body: serde_json::Value::Null,
+            headers: HashMap::new(),
+        }
+    }
+}
+
+
+impl From<Custom<JsonValue>> for SubResponse {
+    fn from(response: Custom<JsonValue>) -> SubResponse {
+        SubResponse { status: response.0.code, body: (response.1).0, headers: HashMap::new() }
+    }
+}
+
+
+impl<T: Into<SubResponse>> From<WithHeaders<T>> for SubResponse {
+    fn from(response: WithHeaders<T>) -> SubResponse {
+        let mut sub_response: SubResponse = response.response.into();
+        sub_response.headers.extend(response.headers);
+        if let Some(status) = response.status {
+            sub_response.status = status.code;
+            if status == Status::NotModified {
+                sub_response.body = serde_json::Value::Null;
+            }
+        }
+        sub_response
+    }
+}
+
+
+impl<T: Into<SubResponse>> From<Result<T, Custom<JsonValue>>> for SubResponse {
+    fn from(response: Result<T, Custom<JsonValue>>) -> SubResponse {
+        match response {
+            Ok(response) => response.into(),
+            Err(error) => error.into(),
+        }
+    }
+}
+
+
+impl<T: Into<SubResponse>> From<Result<T, Failure>> for SubResponse {
+    fn from(response: Result<T, Failure>) -> SubResponse {
+        match response {
+            Ok(response) => response.into(),
+            Err(failure) => SubResponse::error(failure.0),
+        }
+    }
+}
+
+
+pub type BatchHandler<C> = fn(&C, &SubRequest) -> SubResponse;
+
+
+/// Request context of the generated `POST /batch` route.
+pub trait BatchContext {
+    fn batch_routes() -> Vec<Route>;
+}
+
+
+/// Resource handlers reachable from `POST /batch`, managed as Rocket state.
+///
+/// ```rust,ignore
+/// rocket::ignite()
+///     .manage(BatchRoutes::new().add("/warning", Warning::batch))
+///     .mount("/", routes![batch])
+/// ```
+pub struct BatchRoutes<C> {
+    routes: Vec<(String, BatchHandler<C>)>,
+}
+
+
+impl<C> BatchRoutes<C> {
+    pub fn new() -> BatchRoutes<C> {
+        BatchRoutes { routes: vec![] }
+    }
+
+    /// Registers the batch handler of a resource mounted at `endpoint`.
+    pub fn add(mut self, endpoint: &str, handler: BatchHandler<C>) -> BatchRoutes<C> {
+        self.routes.push((endpoint.to_owned(), handler));
+        // Longer endpoints first, so "/" doesn't shadow other resources.
+        self.routes.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        self
+    }
+
+    /// Dispatches a sub-request to the resource mounted at its path.
+    pub fn dispatch(&self, context: &C, request: &SubRequest) -> SubResponse {
+        for &(ref endpoint, handler) in &self.routes {
+            if let Some(relative) = request.relative_to(endpoint) {
+                return handler(context, &relative);
+            }
+        }
+        SubResponse::error(Status::NotFound)
+    }
+}
@@ -0,0 +1,382 @@
+use std::cell::RefCell;
+use std::{iter, slice};
+
+use rocket::{Catcher, Rocket};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::status::Custom;
+use rocket_contrib::JsonValue;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Deserializer, MapAccess, SeqAccess,
+                Visitor};
+use serde_json;
+
+
+/// Application error numbers, more specific than the HTTP status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    Unauthorized = 104,
+    BadJson = 106,
+    InvalidParameters = 107,
+    InvalidPostedData = 109,
+    MissingResource = 111,
+    RequestTooLarge = 113,
+    ModifiedMeanwhile = 114,
+    MethodNotAllowed = 115,
+    ClientReachedCapacity = 117,
+    Forbidden = 121,
+    ConstraintViolated = 122,
+    Backend = 201,
+    Undefined = 999,
+}
+
+
+impl Errno {
+    /// Error number of responses which don't give a more specific one.
+    pub fn from_status(status: Status) -> Errno {
+        match status.code {
+            400 => Errno::InvalidParameters,
+            401 => Errno::Unauthorized,
+            403 => Errno::Forbidden,
+            404 => Errno::MissingResource,
+            405 => Errno::MethodNotAllowed,
+            409 => Errno::ConstraintViolated,
+            412 => Errno::ModifiedMeanwhile,
+            413 => Errno::RequestTooLarge,
+            422 => Errno::InvalidPostedData,
+            429 => Errno::ClientReachedCapacity,
+            503 => Errno::Backend,
+            _ => Errno::Undefined,
+        }
+    }
+}
+
+
+/// Renders the body shared by every error response:
+///
+/// `{"code": 404, "errno": 111, "error": "Not Found", "message": "...", "details": {}}`
+pub fn body(status: Status, errno: Errno, message: &str,
+            details: serde_json::Value) -> serde_json::Value {
+    json!({
+        "code": status.code,
+        "errno": errno as u16,
+        "error": status.reason,
+        "message": message,
+        "details": details,
+    })
+}
+
+
+/// Renders an error response with the shared body.
+pub fn response(status: Status, errno: Errno, message: &str,
+                details: serde_json::Value) -> Custom<JsonValue> {
+    Custom(status, JsonValue(body(status, errno, message, details)))
+}
+
+
+/// Error reading a request body, located as precisely as serde tells: the
+/// position of malformed JSON, or the path of a value of the wrong type.
+#[derive(Debug, Clone)]
+pub struct BodyError {
+    pub message: String,
+    pub syntax: bool,
+    pub path: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub expected: Option<String>,
+}
+
+
+impl BodyError {
+    pub fn new(err: &serde_json::Error) -> BodyError {
+        let mut message = err.to_string();
+        let (line, column) = if err.line() > 0 {
+            let position = format!(" at line {} column {}", err.line(), err.column());
+            if message.ends_with(&position) {
+                let length = message.len() - position.len();
+                message.truncate(length);
+            }
+            (Some(err.line()), Some(err.column()))
+        } else {
+            (None, None)
+        };
+        let syntax = match err.classify() {
+            serde_json::error::Category::Syntax | serde_json::error::Category::Eof => true,
+            _ => false,
+        };
+
+        // Missing and unknown fields are only named in the message.
+        let path = ["missing field `", "unknown field `"].iter().filter_map(|prefix| {
+            if !message.starts_with(prefix) {
+                return None;
+            }
+            message[prefix.len()..].split('`').next().map(|name| name.to_owned())
+        }).next();
+        let expected = message.find(", expected ").map(|start| {
+            message[start + ", expected ".len()..].to_owned()
+        });
+
+        BodyError {
+            message: message,
+            syntax: syntax,
+            path: path,
+            line: line,
+            column: column,
+            expected: expected,
+        }
+    }
+
+    /// Nests the error path in `parent`, e.g. `title` in `2` as `2.title`.
+    pub fn at(mut self, parent: &str) -> BodyError {
+        self.path = Some(match self.path {
+            Some(path) => format!("{}.{}", parent, path),
+            None => parent.to_owned(),
+        });
+        self
+    }
+
+    pub fn body(&self) -> serde_json::Value {
+        let errno = if self.syntax { Errno::BadJson } else { Errno::InvalidPostedData };
+        body(Status::BadRequest, errno, &self.message, json!({
+            "path": self.path,
+            "line": self.line,
+            "column": self.column,
+            "expected": self.expected,
+        }))
+    }
+
+    pub fn response(&self) -> Custom<JsonValue> {
+        Custom(Status::BadRequest, JsonValue(self.body()))
+    }
+}
+
+
+impl From<BodyError> for Custom<JsonValue> {
+    fn from(error: BodyError) -> Custom<JsonValue> {
+        error.response()
+    }
+}
+
+
+/// Reads a request body, locating errors at the path of the offending
+/// value, e.g. `tags.2` or `body.level`.
+pub fn from_value<T: DeserializeOwned>(document: &serde_json::Value) -> Result<T, BodyError> {
+    let path = RefCell::new(vec![]);
+    T::deserialize(PathDeserializer { value: document, path: &path }).map_err(|err| {
+        let error = BodyError::new(&err);
+        let path = path.borrow();
+        if path.is_empty() {
+            error
+        } else {
+            error.at(&path.join("."))
+        }
+    })
+}
+
+
+/// Deserializer of a JSON value keeping track of the path being read. Keys
+/// and indexes are only popped once their value is read, so the path of
+/// the offending value is left on errors. Unknown and missing fields are
+/// left out, as serde names them.
+struct PathDeserializer<'a> {
+    value: &'a serde_json::Value,
+    path: &'a RefCell<Vec<String>>,
+}
+
+
+impl<'de, 'a> Deserializer<'de> for PathDeserializer<'a> {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match *self.value {
+            serde_json::Value::Null => visitor.visit_unit(),
+            serde_json::Value::Bool(value) => visitor.visit_bool(value),
+            serde_json::Value::Number(ref number) => number.clone().deserialize_any(visitor),
+            serde_json::Value::String(ref value) => visitor.visit_str(value),
+            serde_json::Value::Array(ref items) => {
+                visitor.visit_seq(PathSeq { items: items.iter().enumerate(), path: self.path })
+            },
+            serde_json::Value::Object(ref members) => {
+                visitor.visit_map(PathMap { members: members.iter(), value: None, path: self.path })
+            },
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match *self.value {
+            serde_json::Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+            -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str,
+                                         variants: &'static [&'static str], visitor: V)
+            -> Result<V::Value, Self::Error> {
+        self.value.clone().deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+
+struct PathSeq<'a> {
+    items: iter::Enumerate<slice::Iter<'a, serde_json::Value>>,
+    path: &'a RefCell<Vec<String>>,
+}
+
+
+impl<'de, 'a> SeqAccess<'de> for PathSeq<'a> {
+    type Error = serde_json::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T)
+            -> Result<Option<T::Value>, Self::Error> {
+        let (index, value) = match self.items.next() {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        self.path.borrow_mut().push(index.to_string());
+        let item = seed.deserialize(PathDeserializer { value: value, path: self.path })?;
+        self.path.borrow_mut().pop();
+        Ok(Some(item))
+    }
+}
+
+
+struct PathMap<'a> {
+    members: serde_json::map::Iter<'a>,
+    value: Option<&'a serde_json::Value>,
+    path: &'a RefCell<Vec<String>>,
+}
+
+
+impl<'de, 'a> MapAccess<'de> for PathMap<'a> {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+            -> Result<Option<K::Value>, Self::Error> {
+        let (name, value) = match self.members.next() {
+            Some(member) => member,
+            None => return Ok(None),
+        };
+        let key = seed.deserialize(serde_json::Value::String(name.clone()))?;
+        self.path.borrow_mut().push(name.clone());
+        self.value = Some(value);
+        Ok(Some(key))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V)
+            -> Result<V::Value, Self::Error> {
+        let value = match self.value.take() {
+            Some(value) => value,
+            None => return Err(<serde_json::Error as de::Error>::custom("value is missing")),
+        };
+        let value = seed.deserialize(PathDeserializer { value: value, path: self.path })?;
+        self.path.borrow_mut().pop();
+        Ok(value)
+    }
+}
+
+
+fn catch(status: Status, message: &str) -> Custom<JsonValue> {
+    response(status, Errno::from_status(status), message, json!({}))
+}
+
+
+#[catch(400)]
+fn bad_request() -> Custom<JsonValue> {
+    catch(Status::BadRequest, "The request can't be understood.")
+}
+
+
+#[catch(401)]
+fn unauthorized() -> Custom<JsonValue> {
+    catch(Status::Unauthorized, "Please authenticate yourself to use this endpoint.")
+}
+
+
+#[catch(403)]
+fn forbidden() -> Custom<JsonValue> {
+    catch(Status::Forbidden, "This user cannot access this resource.")
+}
+
+
+#[catch(404)]
+fn not_found(request: &Request) -> Custom<JsonValue> {
+    response(Status::NotFound, Errno::MissingResource, "The resource was not found.",
+             json!({"path": request.uri().path()}))
+}
+
+
+#[catch(405)]
+fn method_not_allowed(request: &Request) -> Custom<JsonValue> {
+    response(Status::MethodNotAllowed, Errno::MethodNotAllowed,
+             "Method not allowed on this endpoint.",
+             json!({"method": request.method().as_str(), "path": request.uri().path()}))
+}
+
+
+#[catch(409)]
+fn conflict() -> Custom<JsonValue> {
+    catch(Status::Conflict, "The request conflicts with the stored resources.")
+}
+
+
+#[catch(412)]
+fn precondition_failed() -> Custom<JsonValue> {
+    catch(Status::PreconditionFailed, "Resource was modified meanwhile.")
+}
+
+
+#[catch(413)]
+fn payload_too_large() -> Custom<JsonValue> {
+    catch(Status::PayloadTooLarge, "The request body is too large.")
+}
+
+
+#[catch(422)]
+fn unprocessable_entity() -> Custom<JsonValue> {
+    catch(Status::UnprocessableEntity, "The request body can't be processed.")
+}
+
+
+#[catch(429)]
+fn too_many_requests() -> Custom<JsonValue> {
+    catch(Status::TooManyRequests, "Too many requests, please retry later.")
+}
+
+
+#[catch(500)]
+fn internal_server_error() -> Custom<JsonValue> {
+    catch(Status::InternalServerError, "An internal error occurred.")
+}
+
+
+#[catch(503)]
+fn service_unavailable() -> Custom<JsonValue> {
+    catch(Status::ServiceUnavailable, "Service temporarily unavailable, please retry later.")
+}
+
+
+/// JSON catchers of the common error statuses, using the shared body.
+pub fn catchers() -> Vec<Catcher> {
+    catchers![bad_request, unauthorized, forbidden, not_found, method_not_allowed, conflict,
+              precondition_failed, payload_too_large, unprocessable_entity, too_many_requests,
+              internal_server_error, service_unavailable]
+}
+
+
+/// Registers the JSON catchers on a Rocket instance:
+///
+/// ```ignore
+/// let server = spoilers::errors::register(rocket::ignite()).mount(...);
+/// ```
+pub fn register(rocket: Rocket) -> Rocket {
+    rocket.catch(catchers())
+}
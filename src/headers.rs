@@ -0,0 +1,127 @@
+use rocket;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{Responder, Response};
+use serde::Serialize;
+use serde_json;
+
+
+/// Responder adding raw headers to another responder, e.g. `Next-Page`.
+pub struct WithHeaders<R> {
+    pub response: R,
+    pub headers: Vec<(String, String)>,
+    /// Overrides the status of the response, e.g. `304 Not Modified`.
+    pub status: Option<Status>,
+}
+
+
+impl<R> WithHeaders<R> {
+    pub fn new(response: R) -> WithHeaders<R> {
+        WithHeaders { response: response, headers: vec![], status: None }
+    }
+
+    pub fn status(mut self, status: Status) -> WithHeaders<R> {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn header<V: ToString>(mut self, name: &str, value: V) -> WithHeaders<R> {
+        self.headers.push((name.to_owned(), value.to_string()));
+        self
+    }
+}
+
+
+impl<'r, R: Responder<'r>> Responder<'r> for WithHeaders<R> {
+    fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
+        let mut response = self.response.respond_to(request)?;
+        for (name, value) in self.headers {
+            response.set_raw_header(name, value);
+        }
+        if let Some(status) = self.status {
+            response.set_status(status);
+            if status == Status::NotModified {
+                response.take_body();
+            }
+        }
+        Ok(response)
+    }
+}
+
+
+/// Request guard holding the `If-Match` and `If-None-Match` headers. It
+/// never fails, so routes match with or without them.
+#[derive(Debug, Clone, Default)]
+pub struct Preconditions {
+    pub if_match: Option<String>,
+    pub if_none_match: Option<String>,
+}
+
+
+impl Preconditions {
+    pub fn is_empty(&self) -> bool {
+        self.if_match.is_none() && self.if_none_match.is_none()
+    }
+
+    /// Checks if the write should only create a record, i.e. `If-None-Match: *`.
+    pub fn is_create_only(&self) -> bool {
+        self.if_none_match.as_ref().map_or(false, |if_none_match| if_none_match.trim() == "*")
+    }
+
+    /// Checks preconditions of a write against the ETag of the current
+    /// record, or `None` if it doesn't exist. `If-None-Match: *` only lets
+    /// writes through if there is no record yet.
+    pub fn check(&self, etag: Option<&str>) -> Result<(), Status> {
+        if let Some(ref if_match) = self.if_match {
+            if !etag.map_or(false, |etag| matches(if_match, etag)) {
+                return Err(Status::PreconditionFailed);
+            }
+        }
+        if let Some(ref if_none_match) = self.if_none_match {
+            if etag.map_or(false, |etag| matches(if_none_match, etag)) {
+                return Err(Status::PreconditionFailed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks if a read can be answered with `304 Not Modified`.
+    pub fn is_not_modified(&self, etag: &str) -> bool {
+        self.if_none_match.as_ref().map_or(false, |if_none_match| matches(if_none_match, etag))
+    }
+}
+
+
+/// Checks if a list of entity tags, e.g. `"1500000000000", W/"42"`, or `*`
+/// contains an ETag. Weak tags are compared as strong ones.
+fn matches(tags: &str, etag: &str) -> bool {
+    tags.split(',').map(|tag| tag.trim()).any(|tag| {
+        tag == "*" || tag.trim_left_matches("W/") == etag
+    })
+}
+
+
+/// ETag of a record without a modification time, from its content. Hashed
+/// with 64-bit FNV-1a, which unlike `DefaultHasher` stays the same across
+/// Rust releases, so tags survive upgrades and match between servers.
+pub fn content_etag<T: Serialize>(record: &T) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in serde_json::to_string(record).unwrap_or_default().bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("\"{:x}\"", hash)
+}
+
+
+impl<'a, 'r> FromRequest<'a, 'r> for Preconditions {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Preconditions, ()> {
+        let headers = request.headers();
+        rocket::Outcome::Success(Preconditions {
+            if_match: headers.get_one("If-Match").map(|value| value.to_owned()),
+            if_none_match: headers.get_one("If-None-Match").map(|value| value.to_owned()),
+        })
+    }
+}
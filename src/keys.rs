@@ -0,0 +1,20 @@
+use serde_json;
+pub use uuid::Uuid;
+
+
+/// Generates a server-side UUID key, for `#[primary_key(type="Uuid")]`.
+pub fn new_uuid() -> Uuid {
+    Uuid::new_v4()
+}
+
+
+/// Checks if a record holds every member of a key, e.g. `{"id": 42}` or
+/// `{"device_id": 7, "timestamp": "2017-10-01T00:00:00"}`.
+pub fn matches(record: &serde_json::Value, key: &serde_json::Value) -> bool {
+    match *key {
+        serde_json::Value::Object(ref members) => members.iter().all(|(name, value)| {
+            record.get(name) == Some(value)
+        }),
+        _ => false,
+    }
+}
@@ -1,18 +1,32 @@
 #![feature(plugin, decl_macro, type_ascription, custom_attribute)]
+#![plugin(rocket_codegen)]
 
+pub extern crate base64;
 pub extern crate chrono;
 #[macro_use] pub extern crate diesel;
 #[macro_use] pub extern crate diesel_codegen;
+#[macro_use] pub extern crate log;
 pub extern crate rocket;
 pub extern crate rocket_contrib;
-pub extern crate serde;
+#[macro_use] pub extern crate serde;
 #[macro_use] pub extern crate serde_derive;
 #[macro_use] pub extern crate serde_json;
 pub extern crate redis;
+pub extern crate regex;
 pub extern crate r2d2;
 pub extern crate r2d2_diesel;
 pub extern crate r2d2_redis;
+pub extern crate uuid;
 
+pub mod batch;
+pub mod errors;
+pub mod headers;
+pub mod keys;
 pub mod models;
+pub mod patch;
+pub mod query;
+pub mod queue;
+pub mod search;
 pub mod storage;
+pub mod validate;
 #[cfg(test)] mod tests;
@@ -1,37 +1,80 @@
+use rocket::{Rocket, Route};
+
+use batch::{BatchContext, BatchRoutes, SubRequest, SubResponse};
+
+
+/// Resource generated by `#[derive(Resource)]`, served under its endpoint.
 pub trait Resource {
+    /// Path the routes are mounted at, set with `#[endpoint="/warning"]`.
+    fn endpoint() -> &'static str;
+
+    fn routes() -> Vec<Route>;
 }
 
 
-pub trait CollectionGet: Resource {
-    fn collection_get(&self);
+/// Resource reachable from `POST /batch`, within the request context `C`.
+pub trait BatchResource<C>: Resource {
+    fn batch(context: &C, request: &SubRequest) -> SubResponse;
 }
 
 
-pub trait CollectionCreate: Resource {
-    fn collection_post(&self);
+/// Resources served together, so adding one is a single registration:
+///
+/// ```rust,ignore
+/// let server = Resources::<Context>::new()
+///     .add::<Warning>()
+///     .add::<Error>()
+///     .mount(rocket::ignite());
+/// ```
+///
+/// Every resource is mounted at its endpoint, along with the `POST /batch`
+/// route dispatching to them.
+pub struct Resources<C> {
+    routes: Vec<(&'static str, Vec<Route>)>,
+    batch_routes: BatchRoutes<C>,
+}
+
+
+impl<C: BatchContext + 'static> Resources<C> {
+    pub fn new() -> Resources<C> {
+        Resources { routes: vec![], batch_routes: BatchRoutes::new() }
+    }
+
+    pub fn add<R: BatchResource<C>>(mut self) -> Resources<C> {
+        self.routes.push((R::endpoint(), R::routes()));
+        self.batch_routes = self.batch_routes.add(R::endpoint(), R::batch);
+        self
+    }
+
+    pub fn mount(self, rocket: Rocket) -> Rocket {
+        let rocket = self.routes.into_iter().fold(rocket, |rocket, (endpoint, routes)| {
+            rocket.mount(endpoint, routes)
+        });
+        rocket.mount("/", C::batch_routes()).manage(self.batch_routes)
+    }
 }
 
 
-//trait CollectionDelete: Resource {
-//    fn collection_delete(&self);
-//}
-//
-//
-//trait RecordGet: Resource {
-//    fn record_get(&self);
-//}
-//
-//
-//trait RecordUpate: Resource {
-//    fn record_update(&self);
-//}
-//
-//
-//trait RecordPatch: Resource {
-//    fn record_patch(&self);
-//}
-//
-//
-//trait RecordDelete: Resource {
-//    fn record_delete(&self);
-//}
+// Capabilities of a resource, implemented for the verbs it serves, e.g.
+// `#[resource(verbs="list,create")]`. All verbs are served by default.
+
+/// `GET /` and `HEAD /`, verb `list`.
+pub trait CollectionGet: Resource {}
+
+/// `POST /`, verb `create`.
+pub trait CollectionCreate: Resource {}
+
+/// `DELETE /`, verb `delete_all`.
+pub trait CollectionDelete: Resource {}
+
+/// `GET /<id>`, verb `get`.
+pub trait RecordGet: Resource {}
+
+/// `PUT /<id>`, verb `update`.
+pub trait RecordUpdate: Resource {}
+
+/// `PATCH /<id>` with merge patches and JSON Patch, verb `patch`.
+pub trait RecordPatch: Resource {}
+
+/// `DELETE /<id>`, verb `delete`.
+pub trait RecordDelete: Resource {}
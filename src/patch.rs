@@ -0,0 +1,311 @@
+use serde::{Deserialize, Deserializer};
+use serde_json;
+
+
+/// Deserializes a nullable patch field, telling apart a missing field
+/// (`None`) from an explicit `null` (`Some(None)`).
+pub fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+        where T: Deserialize<'de>, D: Deserializer<'de> {
+    Deserialize::deserialize(deserializer).map(Some)
+}
+
+
+/// Applies a JSON Merge Patch (RFC 7396) to a target document.
+pub fn merge(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let changes = match patch.as_object() {
+        Some(changes) => changes,
+        None => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = json!({});
+    }
+    if let Some(target) = target.as_object_mut() {
+        for (key, value) in changes {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge(target.entry(key.clone()).or_insert(serde_json::Value::Null), value);
+            }
+        }
+    }
+}
+
+
+/// Checks if a merge patch has object members, which must be merged against
+/// the current record instead of replacing it.
+pub fn has_nested(patch: &serde_json::Value) -> bool {
+    patch.as_object().map_or(false, |changes| changes.values().any(|v| v.is_object()))
+}
+
+
+/// Resolves the object members of a merge patch against the current
+/// record, so they can be stored as plain field replacements.
+pub fn merge_nested(patch: &mut serde_json::Value, current: &serde_json::Value) {
+    if let Some(changes) = patch.as_object_mut() {
+        for (key, value) in changes.iter_mut() {
+            if value.is_object() {
+                let mut merged = current[key.as_str()].clone();
+                merge(&mut merged, value);
+                *value = merged;
+            }
+        }
+    }
+}
+
+
+/// Replaces the members of a record by those of a patch already resolved by
+/// `merge_nested`, without merging its objects again.
+pub fn assign(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    if let (Some(target), Some(changes)) = (target.as_object_mut(), patch.as_object()) {
+        for (key, value) in changes {
+            target.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+
+/// A JSON Patch (RFC 6902) operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag="op", rename_all="lowercase")]
+pub enum Operation {
+    Add { path: String, value: serde_json::Value },
+    Remove { path: String },
+    Replace { path: String, value: serde_json::Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: serde_json::Value },
+}
+
+
+impl Operation {
+    pub fn path(&self) -> &str {
+        match *self {
+            Operation::Add { ref path, .. } |
+            Operation::Remove { ref path, .. } |
+            Operation::Replace { ref path, .. } |
+            Operation::Move { ref path, .. } |
+            Operation::Copy { ref path, .. } |
+            Operation::Test { ref path, .. } => path,
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum PatchError {
+    /// The operation path does not exist or can't be created.
+    InvalidPath(String),
+    /// A `test` operation did not match the document.
+    TestFailed(String),
+}
+
+
+/// Splits a JSON Pointer into its unescaped reference tokens.
+fn pointer_tokens(pointer: &str) -> Result<Vec<String>, PatchError> {
+    if pointer.is_empty() {
+        return Ok(vec![]);
+    }
+    if !pointer.starts_with('/') {
+        return Err(PatchError::InvalidPath(pointer.to_owned()));
+    }
+    Ok(pointer[1..].split('/').map(|token| token.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+
+/// Splits a JSON Pointer into its parent pointer and last token.
+fn split_last(pointer: &str) -> Result<(&str, String), PatchError> {
+    match pointer.rfind('/') {
+        Some(index) => {
+            let token = pointer_tokens(&pointer[index..])?.remove(0);
+            Ok((&pointer[..index], token))
+        },
+        None => Err(PatchError::InvalidPath(pointer.to_owned())),
+    }
+}
+
+
+fn add(document: &mut serde_json::Value, pointer: &str, value: serde_json::Value)
+        -> Result<(), PatchError> {
+    if pointer.is_empty() {
+        *document = value;
+        return Ok(());
+    }
+    let (parent, token) = split_last(pointer)?;
+    let invalid = || PatchError::InvalidPath(pointer.to_owned());
+    match document.pointer_mut(parent) {
+        Some(&mut serde_json::Value::Object(ref mut members)) => {
+            members.insert(token, value);
+            Ok(())
+        },
+        Some(&mut serde_json::Value::Array(ref mut items)) => {
+            if token == "-" {
+                items.push(value);
+                return Ok(());
+            }
+            match token.parse::<usize>() {
+                Ok(index) if index <= items.len() => {
+                    items.insert(index, value);
+                    Ok(())
+                },
+                _ => Err(invalid()),
+            }
+        },
+        _ => Err(invalid()),
+    }
+}
+
+
+fn remove(document: &mut serde_json::Value, pointer: &str)
+        -> Result<serde_json::Value, PatchError> {
+    let (parent, token) = split_last(pointer)?;
+    let invalid = || PatchError::InvalidPath(pointer.to_owned());
+    match document.pointer_mut(parent) {
+        Some(&mut serde_json::Value::Object(ref mut members)) => {
+            members.remove(&token).ok_or_else(invalid)
+        },
+        Some(&mut serde_json::Value::Array(ref mut items)) => {
+            match token.parse::<usize>() {
+                Ok(index) if index < items.len() => Ok(items.remove(index)),
+                _ => Err(invalid()),
+            }
+        },
+        _ => Err(invalid()),
+    }
+}
+
+
+fn apply_operation(document: &mut serde_json::Value, operation: &Operation)
+        -> Result<(), PatchError> {
+    match *operation {
+        Operation::Add { ref path, ref value } => add(document, path, value.clone()),
+        Operation::Remove { ref path } => remove(document, path).map(|_| ()),
+        Operation::Replace { ref path, ref value } => {
+            // The root can't be removed, but is replaced like by `add`.
+            if !path.is_empty() {
+                remove(document, path)?;
+            }
+            add(document, path, value.clone())
+        },
+        Operation::Move { ref from, ref path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(PatchError::InvalidPath(path.clone()));
+            }
+            let value = remove(document, from)?;
+            add(document, path, value)
+        },
+        Operation::Copy { ref from, ref path } => {
+            let value = document.pointer(from).cloned()
+                .ok_or_else(|| PatchError::InvalidPath(from.clone()))?;
+            add(document, path, value)
+        },
+        Operation::Test { ref path, ref value } => {
+            match document.pointer(path) {
+                Some(current) if current == value => Ok(()),
+                _ => Err(PatchError::TestFailed(path.clone())),
+            }
+        },
+    }
+}
+
+
+/// Applies JSON Patch operations to a document. Operations are applied as a
+/// whole: the document is left untouched if any of them fails.
+pub fn apply(document: &mut serde_json::Value, operations: &[Operation])
+        -> Result<(), PatchError> {
+    let mut patched = document.clone();
+    for operation in operations {
+        apply_operation(&mut patched, operation)?;
+    }
+    *document = patched;
+    Ok(())
+}
+
+
+/// Splits an operation path into the record field and the path inside it.
+pub fn field_path(pointer: &str) -> Option<(String, Vec<String>)> {
+    let mut tokens = match pointer_tokens(pointer) {
+        Ok(tokens) => tokens,
+        Err(_) => return None,
+    };
+    if tokens.is_empty() {
+        return None;
+    }
+    let field = tokens.remove(0);
+    Some((field, tokens))
+}
+
+
+/// Checks if operations only add, replace or remove object members nested
+/// in the given JSONB fields, so they can be run as atomic `jsonb_set` and
+/// `#-` updates guarded by `guard_sql`. Array positions are left to `apply`,
+/// as `jsonb_set` can't insert into arrays.
+pub fn is_atomic(operations: &[Operation], jsonb_fields: &[&str]) -> bool {
+    !operations.is_empty() && operations.iter().all(|operation| {
+        match *operation {
+            Operation::Add { .. } | Operation::Replace { .. } | Operation::Remove { .. } => {},
+            _ => return false,
+        }
+        match field_path(operation.path()) {
+            Some((field, path)) => {
+                jsonb_fields.contains(&field.as_str()) && !path.is_empty() &&
+                    path.iter().all(|token| token != "-" && token.parse::<usize>().is_err())
+            },
+            None => false,
+        }
+    })
+}
+
+
+/// Renders a text array literal of path tokens, e.g. `ARRAY['a','b']::text[]`.
+fn text_array(tokens: &[String]) -> String {
+    let items: Vec<String> = tokens.iter().map(|token| {
+        format!("'{}'", token.replace('\'', "''"))
+    }).collect();
+    format!("ARRAY[{}]::text[]", items.join(","))
+}
+
+
+/// Renders the condition an atomic operation needs to hold on a JSONB
+/// `column` to follow RFC 6902, given the path inside the column: `add`
+/// needs the parent object, `replace` and `remove` the member itself.
+/// `jsonb_set` and `#-` would otherwise create or skip missing members
+/// silently, so updates are narrowed by this condition and rejected if they
+/// don't match.
+pub fn guard_sql(column: &str, operation: &Operation, path: &[String]) -> String {
+    let column = format!("\"{}\"", column.replace('"', "\"\""));
+    match *operation {
+        Operation::Add { .. } => {
+            let parent = &path[..path.len().saturating_sub(1)];
+            format!("jsonb_typeof({} #> {}) = 'object'", column, text_array(parent))
+        },
+        _ => format!("({} #> {}) IS NOT NULL", column, text_array(path)),
+    }
+}
+
+
+/// Postgres functions used to patch JSONB columns in place. `jsonb_delete_path`
+/// is the function behind the `#-` operator.
+pub mod sql {
+    pub mod jsonb {
+        use diesel::types::{Array, Bool, Jsonb, Text};
+
+        sql_function!(jsonb_set, jsonb_set_t,
+                      (target: Jsonb, path: Array<Text>, new_value: Jsonb, create_missing: Bool)
+                      -> Jsonb);
+        sql_function!(jsonb_delete_path, jsonb_delete_path_t,
+                      (target: Jsonb, path: Array<Text>) -> Jsonb);
+    }
+
+    pub mod nullable_jsonb {
+        use diesel::types::{Array, Bool, Jsonb, Nullable, Text};
+
+        sql_function!(jsonb_set, jsonb_set_t,
+                      (target: Nullable<Jsonb>, path: Array<Text>, new_value: Jsonb,
+                       create_missing: Bool) -> Nullable<Jsonb>);
+        sql_function!(jsonb_delete_path, jsonb_delete_path_t,
+                      (target: Nullable<Jsonb>, path: Array<Text>) -> Nullable<Jsonb>);
+    }
+}
@@ -0,0 +1,729 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use rocket;
+use rocket::http::{RawStr, Status};
+use rocket::request::{FormItems, FromRequest, Outcome};
+use rocket::response::status::Custom;
+use rocket_contrib::JsonValue;
+use base64;
+use serde_json;
+
+use errors::{self, Errno};
+
+
+/// Request guard holding the decoded query string parameters, in order.
+/// It never fails, so routes match with or without a query string.
+pub struct Query {
+    pub params: Vec<(String, String)>,
+}
+
+
+impl Query {
+    /// Parses a raw query string, e.g. `user_id=42&title=disk`.
+    pub fn parse(query: &str) -> Query {
+        let params = FormItems::from(query).map(|(key, value)| {
+            (Query::decode(key), Query::decode(value))
+        }).collect();
+        Query { params: params }
+    }
+
+    /// Decodes a key or value of the query string, with `+` as a space like
+    /// forms encode it, e.g. `?_search=disk+full`.
+    fn decode(raw: &RawStr) -> String {
+        let text = raw.as_str().replace('+', " ");
+        let decoded = RawStr::from_str(&text).url_decode();
+        decoded.unwrap_or(text)
+    }
+
+    /// Gets the value of a parameter.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| value.as_ref())
+    }
+
+    /// Checks if a boolean parameter is set, e.g. `?_partial=true`.
+    pub fn flag(&self, name: &str) -> bool {
+        self.get(name).map_or(false, |value| value == "true" || value == "1")
+    }
+
+    /// Refuses listing parameters, e.g. `?_limit=10` on `DELETE /`, which
+    /// would otherwise be ignored.
+    pub fn deny_list_params(&self) -> Result<(), QueryError> {
+        match self.params.iter().find(|&&(ref name, _)| is_list_param(name)) {
+            Some(&(ref name, _)) => Err(QueryError::ListParameter { name: name.clone() }),
+            None => Ok(()),
+        }
+    }
+}
+
+
+impl<'a, 'r> FromRequest<'a, 'r> for Query {
+    type Error = ();
+
+    fn from_request(request: &'a rocket::Request<'r>) -> Outcome<Query, ()> {
+        rocket::Outcome::Success(Query::parse(request.uri().query().unwrap_or("")))
+    }
+}
+
+
+#[derive(Debug)]
+pub enum QueryError {
+    /// The parameter doesn't match any of the valid names.
+    UnknownParameter { name: String, valid: Vec<String> },
+    /// The parameter value can't be parsed as the field type.
+    InvalidValue { name: String, value: String },
+    /// The operator can't be applied to the field type, e.g. `like_` on integers.
+    UnsupportedOperator { name: String },
+    /// A parameter refers to an unknown field, e.g. `?_sort=-size`.
+    UnknownField { name: String, field: String, valid: Vec<String> },
+    /// A listing parameter is given to a request that doesn't list records.
+    ListParameter { name: String },
+}
+
+
+impl QueryError {
+    pub fn unknown(name: &str, valid: &[&str]) -> QueryError {
+        QueryError::UnknownParameter {
+            name: name.to_owned(),
+            valid: valid.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    /// Renders the error as a `400 Bad Request` response.
+    pub fn response(&self) -> Custom<JsonValue> {
+        let (message, details) = match *self {
+            QueryError::UnknownParameter { ref name, ref valid } => (
+                format!("Unknown parameter `{}`.", name),
+                json!({
+                    "parameter": name,
+                    "valid": valid,
+                    "operators": OPERATOR_PREFIXES.iter().map(|&(prefix, _)| prefix)
+                                                  .collect::<Vec<_>>(),
+                }),
+            ),
+            QueryError::InvalidValue { ref name, ref value } => (
+                format!("Invalid value `{}` for parameter `{}`.", value, name),
+                json!({"parameter": name, "value": value}),
+            ),
+            QueryError::UnsupportedOperator { ref name } => (
+                format!("Parameter `{}` isn't supported by the field type.", name),
+                json!({"parameter": name}),
+            ),
+            QueryError::UnknownField { ref name, ref field, ref valid } => (
+                format!("Unknown field `{}` in parameter `{}`.", field, name),
+                json!({"parameter": name, "field": field, "valid": valid}),
+            ),
+            QueryError::ListParameter { ref name } => (
+                format!("Parameter `{}` only applies to listings.", name),
+                json!({"parameter": name}),
+            ),
+        };
+        errors::response(Status::BadRequest, Errno::InvalidParameters, &message, details)
+    }
+}
+
+
+/// Parses a parameter value as the type of the field it filters.
+pub fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, QueryError> {
+    value.parse().map_err(|_| QueryError::InvalidValue {
+        name: name.to_owned(),
+        value: value.to_owned(),
+    })
+}
+
+
+/// Comparison operators, given as prefixes of the filtered field name,
+/// e.g. `?min_timestamp=2017-01-01T00:00:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operator {
+    Eq,
+    Not,
+    Min,
+    Max,
+    Lt,
+    Gt,
+    In,
+    Like,
+    Has,
+}
+
+
+pub const OPERATOR_PREFIXES: [(&'static str, Operator); 8] = [
+    ("not_", Operator::Not),
+    ("min_", Operator::Min),
+    ("max_", Operator::Max),
+    ("lt_", Operator::Lt),
+    ("gt_", Operator::Gt),
+    ("in_", Operator::In),
+    ("like_", Operator::Like),
+    ("has_", Operator::Has),
+];
+
+
+impl Operator {
+    /// Splits a parameter name into its operator and field. Field names
+    /// are matched first, so a field may itself start with a prefix.
+    pub fn split<'a>(name: &'a str, fields: &[&str]) -> Option<(Operator, &'a str)> {
+        let is_field = |name: &str| fields.iter().any(|field| *field == name);
+        if is_field(name) {
+            return Some((Operator::Eq, name));
+        }
+        OPERATOR_PREFIXES.iter().filter(|&&(prefix, _)| {
+            name.starts_with(prefix) && is_field(&name[prefix.len()..])
+        }).map(|&(prefix, operator)| (operator, &name[prefix.len()..])).next()
+    }
+}
+
+
+/// A filter condition on a field of type `T`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Condition<T> {
+    Eq(T),
+    Not(T),
+    Min(T),
+    Max(T),
+    Lt(T),
+    Gt(T),
+    In(Vec<T>),
+    Like(String),
+    Has(bool),
+}
+
+
+impl<T: FromStr> Condition<T> {
+    /// Parses a condition, given the operators supported by the field type.
+    pub fn parse(operator: Operator, supported: &[Operator], name: &str, value: &str)
+            -> Result<Condition<T>, QueryError> {
+        if !supported.contains(&operator) {
+            return Err(QueryError::UnsupportedOperator { name: name.to_owned() });
+        }
+        Ok(match operator {
+            Operator::Eq => Condition::Eq(parse_value(name, value)?),
+            Operator::Not => Condition::Not(parse_value(name, value)?),
+            Operator::Min => Condition::Min(parse_value(name, value)?),
+            Operator::Max => Condition::Max(parse_value(name, value)?),
+            Operator::Lt => Condition::Lt(parse_value(name, value)?),
+            Operator::Gt => Condition::Gt(parse_value(name, value)?),
+            Operator::In => Condition::In(
+                value.split(',').map(|item| parse_value(name, item)).collect::<Result<_, _>>()?
+            ),
+            Operator::Like => Condition::Like(value.to_owned()),
+            Operator::Has => Condition::Has(parse_value(name, value)?),
+        })
+    }
+}
+
+
+impl<T: PartialEq> Condition<T> {
+    /// Checks an equality condition against a field value. Like in SQL, a
+    /// null value only matches `has_<field>=false`.
+    pub fn matches_eq(&self, value: Option<&T>) -> bool {
+        match (self, value) {
+            (&Condition::Has(expected), value) => value.is_some() == expected,
+            (_, None) => false,
+            (&Condition::Eq(ref expected), Some(value)) => value == expected,
+            (&Condition::Not(ref expected), Some(value)) => value != expected,
+            (&Condition::In(ref expected), Some(value)) => expected.contains(value),
+            _ => true,
+        }
+    }
+}
+
+
+impl<T: PartialOrd + ToString> Condition<T> {
+    /// Checks a condition against a field value, see `matches_eq`.
+    pub fn matches(&self, value: Option<&T>) -> bool {
+        match (self, value) {
+            (&Condition::Min(ref bound), Some(value)) => value >= bound,
+            (&Condition::Max(ref bound), Some(value)) => value <= bound,
+            (&Condition::Lt(ref bound), Some(value)) => value < bound,
+            (&Condition::Gt(ref bound), Some(value)) => value > bound,
+            (&Condition::Like(ref pattern), Some(value)) => like(&value.to_string(), pattern),
+            _ => self.matches_eq(value),
+        }
+    }
+}
+
+
+/// A scalar compared against a member of a JSON document, e.g. `error` in
+/// `?body.level=error`. Values are parsed as JSON, falling back to strings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonScalar(pub serde_json::Value);
+
+
+impl FromStr for JsonScalar {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<JsonScalar, ()> {
+        match serde_json::from_str(value) {
+            Ok(scalar @ serde_json::Value::Number(_)) |
+            Ok(scalar @ serde_json::Value::Bool(_)) |
+            Ok(scalar @ serde_json::Value::Null) => Ok(JsonScalar(scalar)),
+            _ => Ok(JsonScalar(serde_json::Value::String(value.to_owned()))),
+        }
+    }
+}
+
+
+impl PartialOrd for JsonScalar {
+    /// Only values of the same type are ordered, as in `jsonb_typeof` guards.
+    fn partial_cmp(&self, other: &JsonScalar) -> Option<Ordering> {
+        use serde_json::Value;
+        match (&self.0, &other.0) {
+            (&Value::Number(ref a), &Value::Number(ref b)) => {
+                a.as_f64().and_then(|a| b.as_f64().and_then(|b| a.partial_cmp(&b)))
+            },
+            (&Value::String(ref a), &Value::String(ref b)) => a.partial_cmp(b),
+            (&Value::Bool(ref a), &Value::Bool(ref b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+
+impl fmt::Display for JsonScalar {
+    /// Formats the value like the `->>` operator does.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            serde_json::Value::String(ref value) => write!(f, "{}", value),
+            ref value => write!(f, "{}", value),
+        }
+    }
+}
+
+
+/// A condition on a member of a JSON field, e.g. `?gt_body.latency_ms=500`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathCondition {
+    pub field: String,
+    pub path: Vec<String>,
+    pub condition: Condition<JsonScalar>,
+}
+
+
+impl PathCondition {
+    /// Parses a condition on a dotted path of one of the JSON fields, or
+    /// returns `None` if the parameter isn't a path.
+    pub fn parse(name: &str, value: &str, json_fields: &[&str])
+            -> Result<Option<PathCondition>, QueryError> {
+        let split = |name: &str| -> Option<(String, Vec<String>)> {
+            let mut tokens = name.split('.');
+            let field = tokens.next().unwrap_or("");
+            let path: Vec<String> = tokens.map(|token| token.to_owned()).collect();
+            if json_fields.iter().any(|name| *name == field) && !path.is_empty() &&
+                    path.iter().all(|token| !token.is_empty()) {
+                Some((field.to_owned(), path))
+            } else {
+                None
+            }
+        };
+        // Paths are matched first, so a JSON field may itself start with a prefix.
+        let (operator, (field, path)) = match split(name) {
+            Some(split) => (Operator::Eq, split),
+            None => {
+                let prefixed = OPERATOR_PREFIXES.iter().filter(|&&(prefix, _)| {
+                    name.starts_with(prefix)
+                }).filter_map(|&(prefix, operator)| {
+                    split(&name[prefix.len()..]).map(|split| (operator, split))
+                }).next();
+                match prefixed {
+                    Some(prefixed) => prefixed,
+                    None => return Ok(None),
+                }
+            },
+        };
+        let supported = [Operator::Eq, Operator::Not, Operator::Min, Operator::Max, Operator::Lt,
+                         Operator::Gt, Operator::In, Operator::Like, Operator::Has];
+        Ok(Some(PathCondition {
+            field: field,
+            path: path,
+            condition: Condition::parse(operator, &supported, name, value)?,
+        }))
+    }
+
+    /// Renders the member as a chain of `->` operators, with `->>` last to
+    /// get it as text.
+    fn member_sql(&self, text: bool) -> String {
+        let mut sql = format!("\"{}\"", self.field);
+        for (index, token) in self.path.iter().enumerate() {
+            let operator = if text && index == self.path.len() - 1 { "->>" } else { "->" };
+            match token.parse::<usize>() {
+                Ok(position) => sql.push_str(&format!("{}{}", operator, position)),
+                Err(_) => sql.push_str(&format!("{}'{}'", operator, token.replace('\'', "''"))),
+            }
+        }
+        sql
+    }
+
+    /// Renders the condition as SQL. JSON `null` members are handled like
+    /// missing ones, as SQL `NULL`.
+    pub fn sql(&self) -> String {
+        let member = self.member_sql(false);
+        let text = self.member_sql(true);
+        let value = format!("NULLIF({}, 'null'::jsonb)", member);
+        let jsonb = |scalar: &JsonScalar| {
+            format!("'{}'::jsonb", scalar.0.to_string().replace('\'', "''"))
+        };
+        // Members of a different type than the bound don't match.
+        let compare = |operator: &str, bound: &JsonScalar| {
+            let (kind, lhs) = match bound.0 {
+                serde_json::Value::Number(_) => ("number", format!("({})::numeric", text)),
+                serde_json::Value::String(_) => ("string", text.clone()),
+                serde_json::Value::Bool(_) => ("boolean", format!("({})::boolean", text)),
+                _ => return "FALSE".to_owned(),
+            };
+            format!("CASE WHEN jsonb_typeof({}) = '{}' THEN {} {} {} END",
+                    member, kind, lhs, operator, sql_literal(&bound.0).unwrap_or_default())
+        };
+
+        match self.condition {
+            // Containment can be served by a GIN index on the column.
+            Condition::Eq(ref expected) if !expected.0.is_null() &&
+                    self.path.iter().all(|token| token.parse::<usize>().is_err()) => {
+                let document = self.path.iter().rev().fold(expected.0.clone(), |value, token| {
+                    let mut member = serde_json::Map::new();
+                    member.insert(token.clone(), value);
+                    serde_json::Value::Object(member)
+                });
+                format!("\"{}\" @> {}", self.field, jsonb(&JsonScalar(document)))
+            },
+            Condition::Eq(ref expected) => format!("{} = {}", value, jsonb(expected)),
+            Condition::Not(ref expected) => format!("{} <> {}", value, jsonb(expected)),
+            Condition::Min(ref bound) => compare(">=", bound),
+            Condition::Max(ref bound) => compare("<=", bound),
+            Condition::Lt(ref bound) => compare("<", bound),
+            Condition::Gt(ref bound) => compare(">", bound),
+            Condition::In(ref values) if values.is_empty() => "FALSE".to_owned(),
+            Condition::In(ref values) => {
+                let values: Vec<String> = values.iter().map(|value| jsonb(value)).collect();
+                format!("{} IN ({})", value, values.join(", "))
+            },
+            Condition::Like(ref pattern) => {
+                format!("{} LIKE '{}'", text, pattern.replace('\'', "''"))
+            },
+            Condition::Has(true) => format!("{} IS NOT NULL", value),
+            Condition::Has(false) => format!("{} IS NULL", value),
+        }
+    }
+
+    /// Checks the condition against a JSON field of a record in memory.
+    pub fn matches(&self, document: Option<&serde_json::Value>) -> bool {
+        let pointer: String = self.path.iter().map(|token| {
+            format!("/{}", token.replace('~', "~0").replace('/', "~1"))
+        }).collect();
+        let value = match document.and_then(|document| document.pointer(&pointer)) {
+            Some(&serde_json::Value::Null) | None => None,
+            Some(value) => Some(JsonScalar(value.clone())),
+        };
+        self.condition.matches(value.as_ref())
+    }
+}
+
+
+/// Matches a SQL `LIKE` pattern, where `%` matches any sequence and `_`
+/// any single character.
+pub fn like(value: &str, pattern: &str) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+
+    fn matches(value: &[char], pattern: &[char]) -> bool {
+        match pattern.first() {
+            None => value.is_empty(),
+            Some(&'%') => (0..value.len() + 1).any(|skip| matches(&value[skip..], &pattern[1..])),
+            Some(&'_') => !value.is_empty() && matches(&value[1..], &pattern[1..]),
+            Some(c) => value.first() == Some(c) && matches(&value[1..], &pattern[1..]),
+        }
+    }
+
+    matches(&value, &pattern)
+}
+
+
+/// Parameters controlling how a collection is listed, as opposed to filters.
+pub const LIST_PARAMS: [&'static str; 5] = ["_sort", "_limit", "_token", "_count", "_fields"];
+
+
+/// Page size cap of resources not setting `#[max_limit]`.
+pub const DEFAULT_MAX_LIMIT: i64 = 1000;
+
+
+/// Checks if a parameter controls listing instead of filtering records.
+pub fn is_list_param(name: &str) -> bool {
+    LIST_PARAMS.iter().any(|param| *param == name)
+}
+
+
+/// Sort key of a collection, e.g. `-timestamp` for descending order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortKey {
+    pub field: String,
+    pub descending: bool,
+}
+
+
+/// Options of a collection listing, parsed from the query string.
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub sort: Vec<SortKey>,
+    /// Maximum number of records in a page.
+    pub limit: i64,
+    /// Sort key values of the last record of the previous page.
+    pub after: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Fields to be returned, or `None` for all of them.
+    pub fields: Option<Vec<String>>,
+    /// Fields of the primary key, breaking ties between sort keys.
+    pub primary_key: Vec<String>,
+    /// Modification time field, always loaded as it gives the record ETags.
+    pub version: Option<String>,
+    /// Fields holding text, sorted with the `"C"` collation so the database
+    /// compares them bytewise, like records are compared in memory.
+    pub text: Vec<String>,
+}
+
+
+impl ListOptions {
+    /// Parses listing options, given the fields that can be sorted, the
+    /// field sorted by when none is requested, the page size cap and the
+    /// primary key fields, e.g. `?_sort=-timestamp,user_id&_limit=20`.
+    pub fn from_query(query: &Query, sortable: &[&str], default_sort: Option<&str>,
+                      max_limit: i64, primary_key: &[&str])
+            -> Result<ListOptions, QueryError> {
+        let mut options = ListOptions::default();
+        options.limit = max_limit;
+        options.primary_key = primary_key.iter().map(|field| field.to_string()).collect();
+        if let Some(sort) = query.get("_sort") {
+            for key in sort.split(',').map(|key| key.trim()).filter(|key| !key.is_empty()) {
+                let (field, descending) = if key.starts_with('-') {
+                    (&key[1..], true)
+                } else {
+                    (key.trim_left_matches('+'), false)
+                };
+                if !sortable.iter().any(|name| *name == field) {
+                    return Err(QueryError::UnknownField {
+                        name: "_sort".to_owned(),
+                        field: field.to_owned(),
+                        valid: sortable.iter().map(|name| name.to_string()).collect(),
+                    });
+                }
+                options.sort.push(SortKey { field: field.to_owned(), descending: descending });
+            }
+        }
+        if options.sort.is_empty() {
+            if let Some(field) = default_sort {
+                options.sort.push(SortKey { field: field.to_owned(), descending: false });
+            }
+        }
+        if let Some(limit) = query.get("_limit") {
+            let limit: i64 = parse_value("_limit", limit)?;
+            if limit < 1 {
+                return Err(QueryError::InvalidValue {
+                    name: "_limit".to_owned(),
+                    value: limit.to_string(),
+                });
+            }
+            options.limit = ::std::cmp::min(limit, max_limit);
+        }
+        if let Some(token) = query.get("_token") {
+            options.after = Some(options.decode_token(token).ok_or_else(|| {
+                QueryError::InvalidValue { name: "_token".to_owned(), value: token.to_owned() }
+            })?);
+        }
+        Ok(options)
+    }
+
+    /// Checks if a column must be loaded: either it is returned, or it is
+    /// needed to sort, paginate or tag the record.
+    pub fn selects(&self, field: &str) -> bool {
+        match self.fields {
+            Some(ref fields) => {
+                fields.iter().any(|name| name == field) ||
+                    self.keys().iter().any(|key| key.field == field) ||
+                    self.version.as_ref().map_or(false, |version| version == field)
+            },
+            None => true,
+        }
+    }
+
+    /// Keeps only the requested fields of a serialized record.
+    pub fn project(&self, record: serde_json::Value) -> serde_json::Value {
+        match (self.fields.as_ref(), record) {
+            (Some(fields), serde_json::Value::Object(members)) => {
+                serde_json::Value::Object(members.into_iter().filter(|&(ref name, _)| {
+                    fields.iter().any(|field| field == name)
+                }).collect())
+            },
+            (_, record) => record,
+        }
+    }
+
+    /// Sort keys used for pagination: the requested ones, then the primary
+    /// key fields, so every record has a distinct position.
+    pub fn keys(&self) -> Vec<SortKey> {
+        let mut keys = self.sort.clone();
+        for field in &self.primary_key {
+            if !keys.iter().any(|key| key.field == *field) {
+                keys.push(SortKey { field: field.clone(), descending: false });
+            }
+        }
+        keys
+    }
+
+    /// Encodes the position of a record as an opaque `Next-Page` token.
+    pub fn next_token(&self, record: &serde_json::Value) -> String {
+        let position: serde_json::Map<String, serde_json::Value> = self.keys().iter().map(|key| {
+            (key.field.clone(), record[key.field.as_str()].clone())
+        }).collect();
+        base64::encode_config(serde_json::Value::Object(position).to_string().as_bytes(),
+                              base64::URL_SAFE)
+    }
+
+    /// Decodes a token, checking it was issued for the same sort keys.
+    fn decode_token(&self, token: &str) -> Option<serde_json::Map<String, serde_json::Value>> {
+        let decoded = base64::decode_config(token, base64::URL_SAFE).ok()?;
+        let position = match serde_json::from_slice(&decoded).ok()? {
+            serde_json::Value::Object(position) => position,
+            _ => return None,
+        };
+        let keys = self.keys();
+        let valid = position.len() == keys.len() && keys.iter().all(|key| {
+            position.get(&key.field).map_or(false, |value| {
+                !value.is_object() && !value.is_array()
+            })
+        });
+        if valid { Some(position) } else { None }
+    }
+
+    /// Renders the keyset condition selecting records after the token
+    /// position, ordered like `order_by`. Values are rendered as escaped
+    /// SQL literals, as tokens come from clients.
+    pub fn after_sql(&self) -> Option<String> {
+        let position = match self.after {
+            Some(ref position) => position,
+            None => return None,
+        };
+        let keys = self.keys();
+        let column = |key: &SortKey| self.column(key);
+
+        let branches: Vec<String> = (0..keys.len()).map(|index| {
+            let mut terms: Vec<String> = keys[..index].iter().map(|key| {
+                match sql_literal(&position[&key.field]) {
+                    Some(value) => format!("{} = {}", column(key), value),
+                    None => format!("{} IS NULL", column(key)),
+                }
+            }).collect();
+            let key = &keys[index];
+            // Nulls come last in ascending order and first in descending order.
+            terms.push(match (sql_literal(&position[&key.field]), key.descending) {
+                (Some(value), false) => {
+                    format!("({} > {} OR {} IS NULL)", column(key), value, column(key))
+                },
+                (Some(value), true) => format!("{} < {}", column(key), value),
+                (None, false) => "FALSE".to_owned(),
+                (None, true) => format!("{} IS NOT NULL", column(key)),
+            });
+            format!("({})", terms.join(" AND "))
+        }).collect();
+
+        Some(branches.join(" OR "))
+    }
+
+    /// Checks in memory if a record comes after the token position.
+    pub fn is_after(&self, record: &serde_json::Value) -> bool {
+        let position = match self.after {
+            Some(ref position) => position,
+            None => return true,
+        };
+        for key in self.keys() {
+            let ordering = compare_json(&record[key.field.as_str()], &position[&key.field]);
+            let ordering = if key.descending { ordering.reverse() } else { ordering };
+            match ordering {
+                Ordering::Greater => return true,
+                Ordering::Less => return false,
+                Ordering::Equal => continue,
+            }
+        }
+        false
+    }
+
+    /// Renders the `ORDER BY` clause of the sort keys, with the primary key
+    /// breaking ties. Field names are checked when parsed, so they are safe
+    /// to quote as is.
+    pub fn order_by(&self) -> String {
+        let keys: Vec<String> = self.keys().iter().map(|key| {
+            format!("{} {}", self.column(key), if key.descending { "DESC" } else { "ASC" })
+        }).collect();
+        keys.join(", ")
+    }
+
+    /// Renders the column of a sort key, collated bytewise if it holds text.
+    fn column(&self, key: &SortKey) -> String {
+        if self.text.contains(&key.field) {
+            format!("\"{}\" COLLATE \"C\"", key.field)
+        } else {
+            format!("\"{}\"", key.field)
+        }
+    }
+}
+
+
+/// Parses the fields to be returned, e.g. `?_fields=id,timestamp,title`.
+pub fn parse_fields(query: &Query, valid: &[&str]) -> Result<Option<Vec<String>>, QueryError> {
+    let fields = match query.get("_fields") {
+        Some(fields) => fields,
+        None => return Ok(None),
+    };
+    let mut selected = vec![];
+    for field in fields.split(',').map(|field| field.trim()).filter(|field| !field.is_empty()) {
+        if !valid.iter().any(|name| *name == field) {
+            return Err(QueryError::UnknownField {
+                name: "_fields".to_owned(),
+                field: field.to_owned(),
+                valid: valid.iter().map(|name| name.to_string()).collect(),
+            });
+        }
+        selected.push(field.to_owned());
+    }
+    Ok(Some(selected))
+}
+
+
+/// Renders a scalar JSON value as a SQL literal, or `None` for null.
+fn sql_literal(value: &serde_json::Value) -> Option<String> {
+    match *value {
+        serde_json::Value::Null => None,
+        serde_json::Value::Bool(value) => Some(if value { "TRUE" } else { "FALSE" }.to_owned()),
+        serde_json::Value::Number(ref value) => Some(value.to_string()),
+        serde_json::Value::String(ref value) => Some(format!("'{}'", value.replace('\'', "''"))),
+        _ => Some("NULL".to_owned()),
+    }
+}
+
+
+/// Compares JSON serialized field values, see `compare`.
+pub fn compare_json(a: &serde_json::Value, b: &serde_json::Value) -> Ordering {
+    use serde_json::Value;
+    match (a, b) {
+        (&Value::Null, &Value::Null) => Ordering::Equal,
+        (&Value::Null, _) => Ordering::Greater,
+        (_, &Value::Null) => Ordering::Less,
+        (&Value::Number(ref a), &Value::Number(ref b)) => {
+            compare(a.as_f64().as_ref(), b.as_f64().as_ref())
+        },
+        (&Value::String(ref a), &Value::String(ref b)) => a.cmp(b),
+        (&Value::Bool(ref a), &Value::Bool(ref b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+
+/// Compares field values in memory like Postgres does, with nulls sorted
+/// after any value in ascending order.
+pub fn compare<T: PartialOrd>(a: Option<&T>, b: Option<&T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
@@ -0,0 +1,298 @@
+use std::thread;
+use std::time::Duration;
+
+use redis;
+use redis::Commands;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use uuid::Uuid;
+
+use keys;
+use storage::{self, ResourceStorageError};
+
+
+/// How long the provisional ids of synced records keep addressing them, in
+/// seconds.
+const SYNCED_ID_TIMEOUT: usize = 86_400;
+
+/// How long a queue lock is held at most, in milliseconds, in case its
+/// holder dies without releasing it.
+const LOCK_TIMEOUT: usize = 30_000;
+
+/// How many times a queue lock is tried, every 100 milliseconds, before
+/// giving up.
+const LOCK_ATTEMPTS: usize = 100;
+
+/// Releases a lock only if still held by the given token, so a lock that
+/// timed out and was taken meanwhile is left alone.
+const UNLOCK: &str = r"
+    if redis.call('get', KEYS[1]) == ARGV[1] then
+        return redis.call('del', KEYS[1])
+    else
+        return 0
+    end
+";
+
+
+/// Runs `f` holding the lock of a queue, `<queue>:lock`. Syncs and the
+/// in-place rewrites of queued records take it, so records aren't rewritten
+/// or removed while being synced. Pushes don't need it, as a sync only trims
+/// the records it read.
+pub fn locked<T, F>(conn: &redis::Connection, queue: &str, f: F)
+        -> Result<T, ResourceStorageError>
+        where F: FnOnce() -> Result<T, ResourceStorageError> {
+    let lock = format!("{}:lock", queue);
+    let token = Uuid::new_v4().to_string();
+    let mut attempts = 0;
+    loop {
+        let acquired: Option<String> = redis::cmd("SET").arg(&lock).arg(&token)
+            .arg("NX").arg("PX").arg(LOCK_TIMEOUT)
+            .query(conn)?;
+        if acquired.is_some() {
+            break;
+        }
+        attempts += 1;
+        if attempts == LOCK_ATTEMPTS {
+            let reason = format!("The queue {} is locked.", queue);
+            return Err(ResourceStorageError::Unavailable(reason));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let result = f();
+    let _: i32 = redis::Script::new(UNLOCK).key(&lock).arg(&token).invoke(conn)?;
+    result
+}
+
+
+/// Script of `push`, giving a record its provisional id and version along
+/// with pushing it, so queued records are in version order.
+const PUSH: &str = r"
+    local members = {}
+    if ARGV[2] ~= '' then
+        local seq = redis.call('incr', KEYS[2])
+        table.insert(members, cjson.encode(ARGV[2]) .. ':' .. string.format('%d', -seq))
+    end
+    if ARGV[3] ~= '' then
+        local latest = tonumber(redis.call('get', KEYS[3])) or 0
+        local version = math.max(tonumber(ARGV[4]), latest + 1)
+        redis.call('set', KEYS[3], string.format('%d', version))
+        table.insert(members, cjson.encode(ARGV[3]) .. ':' .. string.format('%d', version))
+    end
+    if ARGV[1] ~= '{}' then
+        table.insert(members, string.sub(ARGV[1], 2, -2))
+    end
+    local record = '{' .. table.concat(members, ',') .. '}'
+    redis.call('rpush', KEYS[1], record)
+    return record
+";
+
+/// Script of `next_version`, later than the latest version given and than
+/// the one passed.
+const NEXT_VERSION: &str = r"
+    local latest = tonumber(redis.call('get', KEYS[1])) or 0
+    local version = math.max(tonumber(ARGV[1]), latest + 1)
+    redis.call('set', KEYS[1], string.format('%d', version))
+    return version
+";
+
+
+/// Pushes a record to the end of a queue. Queued records are not stored
+/// yet, so records keyed by a `serial` column are given a provisional
+/// negative id, which keeps addressing them for a day once synced and
+/// given their stored id, see `stored_id`. Records tracking
+/// their modification time in a `version` column are given the next
+/// version, later than the `latest` stored one.
+pub fn push(conn: &redis::Connection, queue: &str, mut record: serde_json::Value,
+            serial: Option<&str>, version: Option<(&str, Option<i64>)>)
+        -> Result<serde_json::Value, ResourceStorageError> {
+    if let Some(members) = record.as_object_mut() {
+        if let Some(serial) = serial {
+            members.remove(serial);
+        }
+        if let Some((name, _)) = version {
+            members.remove(name);
+        }
+    }
+    let pushed: String = redis::Script::new(PUSH)
+        .key(queue).key(format!("{}:seq", queue)).key(format!("{}:version", queue))
+        .arg(record.to_string())
+        .arg(serial.unwrap_or(""))
+        .arg(version.map_or("", |(name, _)| name))
+        .arg(version.map_or(0, |(_, latest)| storage::next_version(latest)))
+        .invoke(conn)?;
+    Ok(serde_json::from_str(&pushed)?)
+}
+
+
+/// Gives the next version of a write to a record of a queue, whether
+/// queued or stored, later than the `latest` stored one.
+pub fn next_version(conn: &redis::Connection, queue: &str, latest: Option<i64>)
+        -> redis::RedisResult<i64> {
+    redis::Script::new(NEXT_VERSION)
+        .key(format!("{}:version", queue))
+        .arg(storage::next_version(latest))
+        .invoke(conn)
+}
+
+
+/// Latest version given to the records of a queue, if any.
+pub fn latest_version(conn: &redis::Connection, queue: &str)
+        -> redis::RedisResult<Option<i64>> {
+    conn.get(format!("{}:version", queue))
+}
+
+
+/// Number of records waiting in a queue.
+pub fn len(conn: &redis::Connection, queue: &str) -> redis::RedisResult<i64> {
+    conn.llen(queue)
+}
+
+
+/// Reads a record as a form, leaving out its `serial` key, e.g. the
+/// provisional id of a queued record.
+pub fn form<T: DeserializeOwned>(mut record: serde_json::Value, serial: Option<&str>)
+        -> serde_json::Result<T> {
+    if let (Some(serial), Some(members)) = (serial, record.as_object_mut()) {
+        members.remove(serial);
+    }
+    serde_json::from_value(record)
+}
+
+
+/// Lists all records waiting in a queue.
+pub fn records(conn: &redis::Connection, queue: &str)
+        -> redis::RedisResult<Vec<serde_json::Value>> {
+    let cached: Vec<String> = conn.lrange(queue, 0, -1)?;
+    Ok(cached.iter().filter_map(|s| serde_json::from_str(s.as_ref()).ok()).collect())
+}
+
+
+/// Looks up a queued record by its key, e.g. `{"id": -3}`.
+pub fn find(conn: &redis::Connection, queue: &str, key: &serde_json::Value)
+        -> redis::RedisResult<Option<serde_json::Value>> {
+    Ok(records(conn, queue)?.into_iter().find(|record| keys::matches(record, key)))
+}
+
+
+/// Modifies a queued record in place by its key, which is kept. The queue
+/// is locked meanwhile, so the record isn't synced halfway.
+pub fn modify<F>(conn: &redis::Connection, queue: &str, key: &serde_json::Value, mut f: F)
+        -> Result<Option<serde_json::Value>, ResourceStorageError>
+        where F: FnMut(&mut serde_json::Value) {
+    locked(conn, queue, || {
+        let cached: Vec<String> = conn.lrange(queue, 0, -1)?;
+        for (index, s) in cached.iter().enumerate() {
+            let mut record: serde_json::Value = match serde_json::from_str(s.as_ref()) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            if keys::matches(&record, key) {
+                f(&mut record);
+                if let Some(members) = key.as_object() {
+                    for (name, value) in members {
+                        record[name.as_str()] = value.clone();
+                    }
+                }
+                let _: () = conn.lset(queue, index as isize, record.to_string())?;
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    })
+}
+
+
+/// Replaces a queued record by its key, which is kept.
+pub fn replace(conn: &redis::Connection, queue: &str, key: &serde_json::Value,
+               record: serde_json::Value)
+        -> Result<Option<serde_json::Value>, ResourceStorageError> {
+    modify(conn, queue, key, |queued| *queued = record.clone())
+}
+
+
+/// Removes all queued records matching a predicate, returning those still
+/// queued when removed.
+pub fn remove_matching<F>(conn: &redis::Connection, queue: &str, predicate: F)
+        -> Result<Vec<serde_json::Value>, ResourceStorageError>
+        where F: Fn(&serde_json::Value) -> bool {
+    locked(conn, queue, || {
+        let cached: Vec<String> = conn.lrange(queue, 0, -1)?;
+        let mut removed = vec![];
+        for s in cached {
+            let record: serde_json::Value = match serde_json::from_str(s.as_ref()) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            if predicate(&record) {
+                let count: i32 = conn.lrem(queue, 1, s)?;
+                if count > 0 {
+                    removed.push(record);
+                }
+            }
+        }
+        Ok(removed)
+    })
+}
+
+
+/// Removes a queued record by its key.
+pub fn remove(conn: &redis::Connection, queue: &str, key: &serde_json::Value)
+        -> Result<Option<serde_json::Value>, ResourceStorageError> {
+    Ok(remove_matching(conn, queue, |record| keys::matches(record, key))?.pop())
+}
+
+
+/// Drops the first `count` records of a queue once synced, setting aside
+/// those the database rejected in its dead letters, `<queue>:dead`, so they
+/// don't block the records queued after them. The provisional `ids` of the
+/// synced records are mapped to their stored ones.
+pub fn synced(conn: &redis::Connection, queue: &str, count: usize, rejected: &[(&str, String)],
+              ids: &[(i64, i64)]) -> redis::RedisResult<()> {
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for &(record, ref reason) in rejected {
+        error!("Rejected a record of {}: {} ({})", queue, reason, record);
+        pipe.rpush(format!("{}:dead", queue), record).ignore();
+    }
+    for &(provisional, stored) in ids {
+        pipe.set_ex(format!("{}:id:{}", queue, provisional), stored, SYNCED_ID_TIMEOUT).ignore();
+    }
+    pipe.ltrim(queue, count as isize, -1).ignore();
+    pipe.query(conn)
+}
+
+
+/// Pairs the provisional id of a queued record, keyed by a `serial` column,
+/// with the id it was stored with.
+pub fn synced_id<T: Serialize>(queued: &str, stored: &T, serial: Option<&str>)
+        -> Option<(i64, i64)> {
+    let queued = serde_json::from_str::<serde_json::Value>(queued).ok();
+    let stored = serde_json::to_value(stored).ok();
+    match (serial, queued, stored) {
+        (Some(serial), Some(queued), Some(stored)) => {
+            match (queued[serial].as_i64(), stored[serial].as_i64()) {
+                (Some(provisional), Some(stored)) => Some((provisional, stored)),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+
+/// Looks up the stored id of a synced record by its provisional one.
+pub fn stored_id(conn: &redis::Connection, queue: &str, provisional: i64)
+        -> redis::RedisResult<Option<i64>> {
+    conn.get(format!("{}:id:{}", queue, provisional))
+}
+
+
+/// Logs a failed sync of a queue, which is left as it is to be retried.
+/// Syncs failing to reach the database are expected now and then.
+pub fn sync_failed(queue: &str, error: &ResourceStorageError) {
+    match *error {
+        ResourceStorageError::Unavailable(_) => warn!("Error syncing {}: {:?}", queue, error),
+        _ => error!("Error syncing {}: {:?}", queue, error),
+    }
+}
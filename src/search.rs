@@ -0,0 +1,61 @@
+/// Text search configuration of the generated `to_tsvector` expressions.
+/// Indexes must be built with the same configuration to be used.
+pub const TEXT_SEARCH_CONFIG: &'static str = "english";
+
+
+/// Renders the document searched by `_q`: the searchable columns joined
+/// as a single `tsvector`.
+pub fn tsvector_sql(fields: &[&str]) -> String {
+    let columns: Vec<String> = fields.iter().map(|field| {
+        format!("coalesce(\"{}\", '')", field)
+    }).collect();
+    format!("to_tsvector('{}', {})", TEXT_SEARCH_CONFIG, columns.join(" || ' ' || "))
+}
+
+
+/// Renders a full-text search condition, e.g. for `?_q=disk full`.
+pub fn sql(fields: &[&str], q: &str) -> String {
+    format!("{} @@ plainto_tsquery('{}', '{}')",
+            tsvector_sql(fields), TEXT_SEARCH_CONFIG, q.replace('\'', "''"))
+}
+
+
+/// Renders a search condition for backends without full-text search: every
+/// term must be contained, case insensitively, in one of the columns.
+pub fn fallback_sql(fields: &[&str], q: &str) -> String {
+    let terms: Vec<String> = terms(q).iter().map(|term| {
+        let pattern = term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+                          .replace('\'', "''");
+        let columns: Vec<String> = fields.iter().map(|field| {
+            format!("\"{}\" ILIKE '%{}%'", field, pattern)
+        }).collect();
+        format!("({})", columns.join(" OR "))
+    }).collect();
+    if terms.is_empty() {
+        return "TRUE".to_owned();
+    }
+    terms.join(" AND ")
+}
+
+
+/// Checks in memory if every term is contained in one of the values, like
+/// `fallback_sql` does.
+pub fn matches(values: &[Option<&str>], q: &str) -> bool {
+    let values: Vec<String> = values.iter().filter_map(|value| {
+        value.map(|value| value.to_lowercase())
+    }).collect();
+    terms(q).iter().all(|term| values.iter().any(|value| value.contains(term.as_str())))
+}
+
+
+/// Renders the statement creating a GIN index for `_q`, to be added to the
+/// table migrations.
+pub fn index_sql(table: &str, fields: &[&str]) -> String {
+    format!("CREATE INDEX {}_search_idx ON {} USING GIN ({});",
+            table, table, tsvector_sql(fields))
+}
+
+
+fn terms(q: &str) -> Vec<String> {
+    q.split_whitespace().map(|term| term.to_lowercase()).collect()
+}
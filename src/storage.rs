@@ -1,14 +1,237 @@
+use std::cmp;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use diesel;
+use r2d2;
+use redis;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{Responder, Response};
+use rocket::response::status::Custom;
+use rocket_contrib::JsonValue;
+use serde_json;
+
+use errors::{self, Errno};
+use patch::Operation;
+use query::ListOptions;
+
+
+/// Errors of storage operations, each rendered with its own HTTP status.
 #[derive(Debug,Serialize,Deserialize)]
-pub struct ResourceStorageError {}
+pub enum ResourceStorageError {
+    /// The record doesn't exist.
+    NotFound(String),
+    /// The write conflicts with the stored records, e.g. a unique key or a
+    /// reference to a missing record.
+    Conflict(String),
+    /// The record can't be stored as given.
+    Validation(String),
+    /// The record changed since the version a write expected.
+    PreconditionFailed(String),
+    /// The write can't be applied to the record, e.g. a JSON Patch path.
+    Unprocessable(String),
+    /// The database or queue can't be reached.
+    Unavailable(String),
+    /// Any other failure of the database or queue.
+    Backend(String),
+}
+
+
+impl ResourceStorageError {
+    pub fn new<T: ToString>(reason: T) -> ResourceStorageError {
+        ResourceStorageError::Backend(reason.to_string())
+    }
+
+    pub fn status(&self) -> Status {
+        match *self {
+            ResourceStorageError::NotFound(_) => Status::NotFound,
+            ResourceStorageError::Conflict(_) => Status::Conflict,
+            ResourceStorageError::Validation(_) => Status::BadRequest,
+            ResourceStorageError::PreconditionFailed(_) => Status::PreconditionFailed,
+            ResourceStorageError::Unprocessable(_) => Status::UnprocessableEntity,
+            ResourceStorageError::Unavailable(_) => Status::ServiceUnavailable,
+            ResourceStorageError::Backend(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        match *self {
+            ResourceStorageError::NotFound(ref reason) |
+            ResourceStorageError::Conflict(ref reason) |
+            ResourceStorageError::Validation(ref reason) |
+            ResourceStorageError::PreconditionFailed(ref reason) |
+            ResourceStorageError::Unprocessable(ref reason) |
+            ResourceStorageError::Unavailable(ref reason) |
+            ResourceStorageError::Backend(ref reason) => reason,
+        }
+    }
+
+    pub fn errno(&self) -> Errno {
+        match *self {
+            ResourceStorageError::NotFound(_) => Errno::MissingResource,
+            ResourceStorageError::Conflict(_) => Errno::ConstraintViolated,
+            ResourceStorageError::Validation(_) => Errno::InvalidPostedData,
+            ResourceStorageError::PreconditionFailed(_) => Errno::ModifiedMeanwhile,
+            ResourceStorageError::Unprocessable(_) => Errno::InvalidPostedData,
+            ResourceStorageError::Unavailable(_) => Errno::Backend,
+            ResourceStorageError::Backend(_) => Errno::Undefined,
+        }
+    }
+
+    /// Renders the error with the body shared by every error response.
+    pub fn body(&self) -> serde_json::Value {
+        errors::body(self.status(), self.errno(), self.reason(), json!({}))
+    }
+
+    /// Renders the error as a JSON response with its status.
+    pub fn response(&self) -> Custom<JsonValue> {
+        Custom(self.status(), JsonValue(self.body()))
+    }
+}
+
+
+impl<'r> Responder<'r> for ResourceStorageError {
+    fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
+        self.response().respond_to(request)
+    }
+}
+
+
+impl From<ResourceStorageError> for Custom<JsonValue> {
+    fn from(error: ResourceStorageError) -> Custom<JsonValue> {
+        error.response()
+    }
+}
+
+
+impl From<diesel::result::Error> for ResourceStorageError {
+    fn from(error: diesel::result::Error) -> ResourceStorageError {
+        use diesel::result::{DatabaseErrorKind, Error};
+
+        let reason = error.to_string();
+        match error {
+            Error::NotFound => ResourceStorageError::NotFound(reason),
+            Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) |
+            Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                ResourceStorageError::Conflict(reason)
+            },
+            Error::DatabaseError(DatabaseErrorKind::UnableToSendCommand, _) => {
+                ResourceStorageError::Unavailable(reason)
+            },
+            Error::SerializationError(_) => ResourceStorageError::Validation(reason),
+            _ => ResourceStorageError::Backend(reason),
+        }
+    }
+}
+
+
+impl From<r2d2::GetTimeout> for ResourceStorageError {
+    fn from(error: r2d2::GetTimeout) -> ResourceStorageError {
+        ResourceStorageError::Unavailable(error.to_string())
+    }
+}
+
+
+impl From<redis::RedisError> for ResourceStorageError {
+    fn from(error: redis::RedisError) -> ResourceStorageError {
+        if error.is_io_error() {
+            ResourceStorageError::Unavailable(error.to_string())
+        } else {
+            ResourceStorageError::Backend(error.to_string())
+        }
+    }
+}
+
+
+/// Records are converted from and to JSON when queued.
+impl From<serde_json::Error> for ResourceStorageError {
+    fn from(error: serde_json::Error) -> ResourceStorageError {
+        ResourceStorageError::Backend(error.to_string())
+    }
+}
+
+
+/// Current time in epoch milliseconds, as stored in `#[last_modified]` fields.
+pub fn current_timestamp() -> i64 {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    elapsed.as_secs() as i64 * 1000 + (elapsed.subsec_nanos() / 1_000_000) as i64
+}
+
+
+/// Version of a write, later than the `latest` one of the collection even
+/// within the same millisecond.
+pub fn next_version(latest: Option<i64>) -> i64 {
+    cmp::max(current_timestamp(), latest.map_or(0, |latest| latest + 1))
+}
+
+
+/// Renders the compare-and-swap condition of a write on the version column.
+pub fn version_sql(column: &str, version: Option<i64>) -> String {
+    match version {
+        Some(version) => format!("\"{}\" = {}", column, version),
+        None => "TRUE".to_owned(),
+    }
+}
+
 
+// Storage of a resource, split by the capabilities of the verbs it serves
+// so backends implement every method their handlers call. Writes given a
+// `version` only apply if the `#[last_modified]` value of the record still
+// matches it, returning `None` otherwise. Records are addressed by `Key`,
+// the type of their primary key.
 
-pub trait ResourceStorage<Form, Model, Filters>{
+/// Storage of `POST /`.
+pub trait CreateStorage<Form, Model> {
     fn create<'a>(&self, form: Form)
-        -> Result<Model,ResourceStorageError>;
+            -> Result<Model,ResourceStorageError>;
 
     fn bulk_create<'a>(&self, form: Vec<Form>)
-        -> Result<Model,ResourceStorageError>;
+            -> Result<Vec<Model>,ResourceStorageError>;
+}
+
+/// Storage of `GET /`.
+pub trait ListStorage<Model, Filters> {
+    fn list<'a>(&self, filters: Filters, options: &ListOptions)
+            -> Result<Vec<Model>,ResourceStorageError>;
+
+    fn count<'a>(&self, filters: Filters)
+            -> Result<i64,ResourceStorageError>;
+
+    /// Latest version of the collection and its number of records, which
+    /// change with every write, if records track their modification time.
+    fn timestamp<'a>(&self)
+            -> Result<Option<(i64, i64)>,ResourceStorageError>;
+}
+
+/// Storage of `GET /<id>`, also read by writes checking their preconditions.
+pub trait GetStorage<Model, Key> {
+    fn get<'a>(&self, id: Key)
+            -> Result<Option<Model>,ResourceStorageError>;
+}
+
+/// Storage of `PUT /<id>`.
+pub trait UpdateStorage<Form, Model, Key> {
+    fn update<'a>(&self, id: Key, form: Form, version: Option<i64>)
+            -> Result<Option<Model>,ResourceStorageError>;
+}
+
+/// Storage of `PATCH /<id>`.
+pub trait PatchStorage<Model, Patch, Key> {
+    fn patch<'a>(&self, id: Key, patch: Patch, version: Option<i64>)
+            -> Result<Option<Model>,ResourceStorageError>;
+
+    fn json_patch<'a>(&self, id: Key, operations: Vec<Operation>, version: Option<i64>)
+            -> Result<Option<Model>,ResourceStorageError>;
+}
+
+/// Storage of `DELETE /<id>`.
+pub trait DeleteStorage<Model, Key> {
+    fn delete<'a>(&self, id: Key, version: Option<i64>)
+            -> Result<Option<Model>,ResourceStorageError>;
+}
 
-    fn list<'a>(&self, filters: Filters)
-        -> Result<Vec<Model>,ResourceStorageError>;
+/// Storage of `DELETE /`.
+pub trait DeleteManyStorage<Model, Filters> {
+    fn delete_many<'a>(&self, filters: Filters)
+            -> Result<Vec<Model>,ResourceStorageError>;
 }
@@ -0,0 +1,866 @@
+mod patch {
+    use serde_json;
+
+    use patch::{self, Operation, PatchError};
+
+
+    fn operations(value: serde_json::Value) -> Vec<Operation> {
+        serde_json::from_value(value).unwrap()
+    }
+
+
+    fn applied(mut document: serde_json::Value, value: serde_json::Value)
+            -> Result<serde_json::Value, PatchError> {
+        patch::apply(&mut document, &operations(value)).map(|()| document)
+    }
+
+
+    fn is_invalid_path<T>(result: &Result<T, PatchError>) -> bool {
+        match *result {
+            Err(PatchError::InvalidPath(_)) => true,
+            _ => false,
+        }
+    }
+
+
+    #[test]
+    fn add_inserts_members_and_items() {
+        let document = applied(json!({"body": {"tags": ["a"]}}), json!([
+            {"op": "add", "path": "/body/level", "value": "error"},
+            {"op": "add", "path": "/body/tags/0", "value": "first"},
+            {"op": "add", "path": "/body/tags/-", "value": "last"}
+        ])).unwrap();
+        assert_eq!(document, json!({"body": {"level": "error", "tags": ["first", "a", "last"]}}));
+    }
+
+    #[test]
+    fn add_needs_the_parent() {
+        let result = applied(json!({"body": {}}), json!([
+            {"op": "add", "path": "/body/missing/level", "value": 1}
+        ]));
+        assert!(is_invalid_path(&result));
+    }
+
+    #[test]
+    fn remove_deletes_members_and_items() {
+        let document = applied(json!({"body": {"level": 1, "tags": ["a", "b"]}}), json!([
+            {"op": "remove", "path": "/body/level"},
+            {"op": "remove", "path": "/body/tags/0"}
+        ])).unwrap();
+        assert_eq!(document, json!({"body": {"tags": ["b"]}}));
+    }
+
+    #[test]
+    fn remove_needs_the_member() {
+        let result = applied(json!({"body": {}}), json!([
+            {"op": "remove", "path": "/body/level"}
+        ]));
+        assert!(is_invalid_path(&result));
+    }
+
+    #[test]
+    fn replace_needs_the_member() {
+        let document = applied(json!({"body": {"level": 1}}), json!([
+            {"op": "replace", "path": "/body/level", "value": 2}
+        ])).unwrap();
+        assert_eq!(document, json!({"body": {"level": 2}}));
+
+        let result = applied(json!({"body": {}}), json!([
+            {"op": "replace", "path": "/body/level", "value": 2}
+        ]));
+        assert!(is_invalid_path(&result));
+    }
+
+    #[test]
+    fn replace_at_the_root_replaces_the_document() {
+        let document = applied(json!({"body": {"level": 1}}), json!([
+            {"op": "replace", "path": "", "value": {"title": "disk"}},
+            {"op": "add", "path": "/level", "value": 2}
+        ])).unwrap();
+        assert_eq!(document, json!({"title": "disk", "level": 2}));
+
+        let result = applied(json!({"body": {}}), json!([{"op": "remove", "path": ""}]));
+        assert!(is_invalid_path(&result));
+    }
+
+    #[test]
+    fn move_and_copy_members() {
+        let document = applied(json!({"body": {"a": 1}}), json!([
+            {"op": "copy", "from": "/body/a", "path": "/body/b"},
+            {"op": "move", "from": "/body/a", "path": "/body/c"}
+        ])).unwrap();
+        assert_eq!(document, json!({"body": {"b": 1, "c": 1}}));
+    }
+
+    #[test]
+    fn move_into_itself_fails() {
+        let result = applied(json!({"body": {"a": {}}}), json!([
+            {"op": "move", "from": "/body/a", "path": "/body/a/b"}
+        ]));
+        assert!(is_invalid_path(&result));
+    }
+
+    #[test]
+    fn test_compares_values() {
+        assert!(applied(json!({"level": 1}), json!([
+            {"op": "test", "path": "/level", "value": 1}
+        ])).is_ok());
+
+        let result = applied(json!({"level": 1}), json!([
+            {"op": "test", "path": "/level", "value": 2}
+        ]));
+        match result {
+            Err(PatchError::TestFailed(path)) => assert_eq!(path, "/level"),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pointers_are_unescaped() {
+        let document = applied(json!({"body": {}}), json!([
+            {"op": "add", "path": "/body/a~1b~0c", "value": 1}
+        ])).unwrap();
+        assert_eq!(document, json!({"body": {"a/b~c": 1}}));
+    }
+
+    #[test]
+    fn failed_operations_leave_the_document_untouched() {
+        let mut document = json!({"body": {"level": 1}});
+        let result = patch::apply(&mut document, &operations(json!([
+            {"op": "replace", "path": "/body/level", "value": 2},
+            {"op": "remove", "path": "/body/missing"}
+        ])));
+        assert!(result.is_err());
+        assert_eq!(document, json!({"body": {"level": 1}}));
+    }
+
+    #[test]
+    fn merge_removes_null_members() {
+        let mut document = json!({"body": {"level": 1, "nested": {"a": 1, "b": 2}}, "name": "x"});
+        patch::merge(&mut document, &json!({"body": {"level": null, "nested": {"a": null}}}));
+        assert_eq!(document, json!({"body": {"nested": {"b": 2}}, "name": "x"}));
+    }
+
+    #[test]
+    fn merge_replaces_non_objects() {
+        let mut document = json!({"tags": ["a"], "level": 1});
+        patch::merge(&mut document, &json!({"tags": ["b"], "level": {"value": 2}}));
+        assert_eq!(document, json!({"tags": ["b"], "level": {"value": 2}}));
+    }
+
+    #[test]
+    fn is_atomic_needs_nested_object_members() {
+        let atomic = |value| patch::is_atomic(&operations(value), &["body"]);
+
+        assert!(atomic(json!([
+            {"op": "add", "path": "/body/level", "value": 1},
+            {"op": "replace", "path": "/body/nested/level", "value": 1},
+            {"op": "remove", "path": "/body/old"}
+        ])));
+        assert!(!atomic(json!([])));
+        assert!(!atomic(json!([{"op": "add", "path": "/body", "value": {}}])));
+        assert!(!atomic(json!([{"op": "add", "path": "/name/level", "value": 1}])));
+        assert!(!atomic(json!([{"op": "add", "path": "/body/tags/0", "value": 1}])));
+        assert!(!atomic(json!([{"op": "add", "path": "/body/tags/-", "value": 1}])));
+        assert!(!atomic(json!([{"op": "test", "path": "/body/level", "value": 1}])));
+        assert!(!atomic(json!([{"op": "move", "from": "/body/a", "path": "/body/b"}])));
+    }
+
+    #[test]
+    fn guard_sql_checks_the_parent_or_the_member() {
+        let path = vec!["it's".to_owned(), "level".to_owned()];
+        let add = Operation::Add { path: "/body/it's/level".to_owned(), value: json!(1) };
+        let remove = Operation::Remove { path: "/body/it's/level".to_owned() };
+        assert_eq!(patch::guard_sql("body", &add, &path),
+                   "jsonb_typeof(\"body\" #> ARRAY['it''s']::text[]) = 'object'");
+        assert_eq!(patch::guard_sql("body", &remove, &path),
+                   "(\"body\" #> ARRAY['it''s','level']::text[]) IS NOT NULL");
+    }
+}
+
+
+mod pagination {
+    use base64;
+    use rocket::http::Status;
+    use serde_json;
+
+    use query::{ListOptions, Query, QueryError};
+
+
+    fn options(params: &[(&str, &str)]) -> Result<ListOptions, QueryError> {
+        let query = Query {
+            params: params.iter().map(|&(name, value)| (name.to_owned(), value.to_owned()))
+                          .collect(),
+        };
+        ListOptions::from_query(&query, &["timestamp", "title", "id"], None, 100, &["id"])
+    }
+
+
+    fn after(sort: &str, position: serde_json::Value) -> ListOptions {
+        let mut options = options(&[("_sort", sort)]).unwrap();
+        options.after = position.as_object().cloned();
+        options
+    }
+
+
+    #[test]
+    fn query_strings_decode_plus_as_space() {
+        let query = Query::parse("_search=disk+full&title=a%2Bb&has+space=1");
+        assert_eq!(query.get("_search"), Some("disk full"));
+        assert_eq!(query.get("title"), Some("a+b"));
+        assert_eq!(query.get("has space"), Some("1"));
+    }
+
+    #[test]
+    fn list_params_can_be_denied() {
+        let query = Query::parse("user_id=42&_limit=10");
+        let response = query.deny_list_params().unwrap_err().response();
+        assert_eq!(response.0, Status::BadRequest);
+        assert_eq!((response.1).0["details"]["parameter"], json!("_limit"));
+
+        assert!(Query::parse("user_id=42&_q=disk").deny_list_params().is_ok());
+    }
+
+    #[test]
+    fn tokens_round_trip() {
+        let options = options(&[("_sort", "-timestamp")]).unwrap();
+        let token = options.next_token(&json!({"id": 3, "timestamp": 10, "title": "disk"}));
+        let next = self::options(&[("_sort", "-timestamp"), ("_token", &token)]).unwrap();
+        assert_eq!(next.after, json!({"timestamp": 10, "id": 3}).as_object().cloned());
+    }
+
+    #[test]
+    fn primary_key_breaks_ties() {
+        let options = options(&[("_sort", "-timestamp,title")]).unwrap();
+        assert_eq!(options.order_by(), "\"timestamp\" DESC, \"title\" ASC, \"id\" ASC");
+    }
+
+    #[test]
+    fn after_sql_follows_every_sort_key() {
+        let options = after("-timestamp,title", json!({"timestamp": 10, "title": "it's", "id": 3}));
+        assert_eq!(options.after_sql().unwrap(), concat!(
+            "(\"timestamp\" < 10) OR ",
+            "(\"timestamp\" = 10 AND (\"title\" > 'it''s' OR \"title\" IS NULL)) OR ",
+            "(\"timestamp\" = 10 AND \"title\" = 'it''s' AND (\"id\" > 3 OR \"id\" IS NULL))"
+        ));
+    }
+
+    #[test]
+    fn text_is_sorted_bytewise() {
+        let mut options = after("title", json!({"title": "disk", "id": 3}));
+        options.text = vec!["title".to_owned()];
+        assert_eq!(options.order_by(), "\"title\" COLLATE \"C\" ASC, \"id\" ASC");
+        assert_eq!(options.after_sql().unwrap(), concat!(
+            "((\"title\" COLLATE \"C\" > 'disk' OR \"title\" COLLATE \"C\" IS NULL)) OR ",
+            "(\"title\" COLLATE \"C\" = 'disk' AND (\"id\" > 3 OR \"id\" IS NULL))"
+        ));
+        // Uppercase letters come first, like in the database.
+        assert!(options.is_after(&json!({"title": "disks", "id": 1})));
+        assert!(!options.is_after(&json!({"title": "Zip", "id": 1})));
+    }
+
+    #[test]
+    fn after_sql_sorts_nulls_last() {
+        let ascending = after("title", json!({"title": null, "id": 3}));
+        assert_eq!(ascending.after_sql().unwrap(),
+                   "(FALSE) OR (\"title\" IS NULL AND (\"id\" > 3 OR \"id\" IS NULL))");
+
+        let descending = after("-title", json!({"title": null, "id": 3}));
+        assert_eq!(descending.after_sql().unwrap(), concat!(
+            "(\"title\" IS NOT NULL) OR ",
+            "(\"title\" IS NULL AND (\"id\" > 3 OR \"id\" IS NULL))"
+        ));
+    }
+
+    #[test]
+    fn is_after_matches_after_sql() {
+        let options = after("-timestamp", json!({"timestamp": 10, "id": 3}));
+        assert!(options.is_after(&json!({"timestamp": 9, "id": 1})));
+        assert!(options.is_after(&json!({"timestamp": 10, "id": 4})));
+        assert!(!options.is_after(&json!({"timestamp": 10, "id": 3})));
+        assert!(!options.is_after(&json!({"timestamp": 11, "id": 5})));
+        assert!(!options.is_after(&json!({"timestamp": null, "id": 5})));
+
+        let options = after("timestamp", json!({"timestamp": 10, "id": 3}));
+        assert!(options.is_after(&json!({"timestamp": null, "id": 1})));
+        assert!(!options.is_after(&json!({"timestamp": 9, "id": 5})));
+    }
+
+    #[test]
+    fn malformed_tokens_are_bad_requests() {
+        let not_json = base64::encode_config(b"{\"id\":", base64::URL_SAFE);
+        let other_keys = base64::encode_config(b"{\"title\":\"disk\",\"id\":3}", base64::URL_SAFE);
+        let nested = base64::encode_config(b"{\"timestamp\":{},\"id\":3}", base64::URL_SAFE);
+
+        for token in &["not base64!", not_json.as_str(), other_keys.as_str(), nested.as_str()] {
+            match options(&[("_sort", "timestamp"), ("_token", *token)]) {
+                Err(err) => {
+                    let response = err.response();
+                    assert_eq!(response.0, Status::BadRequest);
+                    assert_eq!((response.1).0["details"]["parameter"], json!("_token"));
+                },
+                Ok(_) => panic!("Token `{}` was accepted", token),
+            }
+        }
+    }
+}
+
+
+mod conditions {
+    use query::{self, Condition, JsonScalar, Operator, PathCondition, QueryError};
+
+
+    fn path_condition(name: &str, value: &str) -> PathCondition {
+        PathCondition::parse(name, value, &["body"]).unwrap().unwrap()
+    }
+
+
+    #[test]
+    fn operators_are_split_from_fields() {
+        let fields = ["timestamp", "not_found"];
+        assert_eq!(Operator::split("timestamp", &fields), Some((Operator::Eq, "timestamp")));
+        assert_eq!(Operator::split("min_timestamp", &fields), Some((Operator::Min, "timestamp")));
+        assert_eq!(Operator::split("in_timestamp", &fields), Some((Operator::In, "timestamp")));
+        assert_eq!(Operator::split("not_found", &fields), Some((Operator::Eq, "not_found")));
+        assert_eq!(Operator::split("after_timestamp", &fields), None);
+        assert_eq!(Operator::split("min_size", &fields), None);
+    }
+
+    #[test]
+    fn conditions_parse_values() {
+        let supported = [Operator::Eq, Operator::In, Operator::Has];
+        match Condition::<i64>::parse(Operator::Eq, &supported, "user_id", "42").unwrap() {
+            Condition::Eq(42) => {},
+            other => panic!("Unexpected condition {:?}", other),
+        }
+        match Condition::<i64>::parse(Operator::In, &supported, "in_user_id", "1,2,3").unwrap() {
+            Condition::In(ref values) if *values == vec![1, 2, 3] => {},
+            other => panic!("Unexpected condition {:?}", other),
+        }
+        match Condition::<i64>::parse(Operator::Has, &supported, "has_user_id", "false").unwrap() {
+            Condition::Has(false) => {},
+            other => panic!("Unexpected condition {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let supported = [Operator::Eq, Operator::In];
+        match Condition::<i64>::parse(Operator::In, &supported, "in_user_id", "1,two") {
+            Err(QueryError::InvalidValue { ref name, ref value }) => {
+                assert_eq!(name, "in_user_id");
+                assert_eq!(value, "two");
+            },
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unsupported_operators_are_rejected() {
+        match Condition::<i64>::parse(Operator::Like, &[Operator::Eq], "like_user_id", "4%") {
+            Err(QueryError::UnsupportedOperator { ref name }) => assert_eq!(name, "like_user_id"),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn path_conditions_need_a_json_field_and_a_path() {
+        let condition = path_condition("gt_body.latency_ms", "500");
+        assert_eq!(condition.field, "body");
+        assert_eq!(condition.path, vec!["latency_ms".to_owned()]);
+        match condition.condition {
+            Condition::Gt(JsonScalar(ref bound)) => assert_eq!(*bound, json!(500)),
+            ref other => panic!("Unexpected condition {:?}", other),
+        }
+
+        for name in &["body", "body.", "body..level", "title.level", "after_body.level"] {
+            assert!(PathCondition::parse(name, "1", &["body"]).unwrap().is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn path_condition_sql_escapes_quotes() {
+        assert_eq!(path_condition("body.name", "it's").sql(),
+                   "\"body\" @> '{\"name\":\"it''s\"}'::jsonb");
+        assert_eq!(path_condition("body.it's", "1").sql(),
+                   "\"body\" @> '{\"it''s\":1}'::jsonb");
+        assert_eq!(path_condition("not_body.it's", "it's").sql(),
+                   "NULLIF(\"body\"->'it''s', 'null'::jsonb) <> '\"it''s\"'::jsonb");
+        assert_eq!(path_condition("min_body.it's", "it's").sql(), concat!(
+            "CASE WHEN jsonb_typeof(\"body\"->'it''s') = 'string' ",
+            "THEN \"body\"->>'it''s' >= 'it''s' END"
+        ));
+    }
+
+    #[test]
+    fn path_condition_sql_keeps_like_wildcards() {
+        assert_eq!(path_condition("like_body.tags.0", "it's %_x").sql(),
+                   "\"body\"->'tags'->>0 LIKE 'it''s %_x'");
+    }
+
+    #[test]
+    fn path_condition_sql_compares_same_types() {
+        assert_eq!(path_condition("gt_body.latency_ms", "500").sql(), concat!(
+            "CASE WHEN jsonb_typeof(\"body\"->'latency_ms') = 'number' ",
+            "THEN (\"body\"->>'latency_ms')::numeric > 500 END"
+        ));
+        assert_eq!(path_condition("in_body.level", "1,error").sql(), concat!(
+            "NULLIF(\"body\"->'level', 'null'::jsonb) IN ('1'::jsonb, '\"error\"'::jsonb)"
+        ));
+    }
+
+    #[test]
+    fn like_matches_wildcards() {
+        assert!(query::like("disk full", "disk%"));
+        assert!(query::like("disk full", "d_sk%"));
+        assert!(query::like("disk", "disk%"));
+        assert!(query::like("50%", "50%"));
+        assert!(!query::like("disk", "disk_"));
+        assert!(!query::like("Disk", "disk"));
+    }
+
+    #[test]
+    fn path_conditions_match_documents() {
+        let document = json!({"level": "error", "latency_ms": 700, "tags": ["disk"], "x": null});
+        assert!(path_condition("body.level", "error").matches(Some(&document)));
+        assert!(path_condition("gt_body.latency_ms", "500").matches(Some(&document)));
+        assert!(!path_condition("gt_body.level", "500").matches(Some(&document)));
+        assert!(path_condition("like_body.tags.0", "d%").matches(Some(&document)));
+        assert!(path_condition("has_body.x", "false").matches(Some(&document)));
+        assert!(!path_condition("body.level", "error").matches(None));
+    }
+}
+
+
+mod body_errors {
+    use serde_json;
+
+    use errors::{self, BodyError};
+
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Event {
+        title: String,
+        level: i64,
+    }
+
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Incident {
+        title: String,
+        events: Vec<Event>,
+        owner: Option<Event>,
+    }
+
+
+    fn body_error(body: &str) -> BodyError {
+        BodyError::new(&serde_json::from_str::<Event>(body).unwrap_err())
+    }
+
+
+    fn incident_error(document: serde_json::Value) -> BodyError {
+        errors::from_value::<Incident>(&document).unwrap_err()
+    }
+
+
+    #[test]
+    fn unknown_fields_are_named() {
+        let error = body_error(r#"{"size": 1}"#);
+        assert_eq!(error.path, Some("size".to_owned()));
+        assert_eq!(error.expected, Some("`title` or `level`".to_owned()));
+
+        let body = error.at("2").body();
+        assert_eq!(body["code"], json!(400));
+        assert_eq!(body["errno"], json!(109));
+        assert_eq!(body["message"], json!("unknown field `size`, expected `title` or `level`"));
+        assert_eq!(body["details"]["path"], json!("2.size"));
+        assert_eq!(body["details"]["line"], json!(1));
+    }
+
+    #[test]
+    fn missing_fields_are_named() {
+        let body = body_error(r#"{"title": "disk full"}"#).body();
+        assert_eq!(body["errno"], json!(109));
+        assert_eq!(body["details"]["path"], json!("level"));
+        assert_eq!(body["details"]["expected"], json!(null));
+    }
+
+    #[test]
+    fn wrong_types_tell_the_expected_one() {
+        let error = errors::from_value::<Event>(&json!({"title": 1, "level": 1})).unwrap_err();
+        assert!(!error.syntax);
+        assert_eq!(error.path, Some("title".to_owned()));
+        assert_eq!(error.line, None);
+
+        let body = error.body();
+        assert_eq!(body["errno"], json!(109));
+        assert_eq!(body["message"], json!("invalid type: integer `1`, expected a string"));
+        assert_eq!(body["details"]["path"], json!("title"));
+        assert_eq!(body["details"]["expected"], json!("a string"));
+    }
+
+    #[test]
+    fn nested_errors_are_located() {
+        let event = json!({"title": "disk full", "level": 1});
+        let wrong_type = json!({"title": "disk full", "level": "high"});
+
+        let error = incident_error(json!({
+            "title": "outage", "events": [event.clone(), wrong_type.clone()], "owner": null
+        }));
+        assert_eq!(error.path, Some("events.1.level".to_owned()));
+        assert_eq!(error.expected, Some("i64".to_owned()));
+
+        let error = incident_error(json!({"title": "outage", "events": [], "owner": wrong_type}));
+        assert_eq!(error.path, Some("owner.level".to_owned()));
+
+        let error = incident_error(json!({
+            "title": "outage", "events": [event, {"title": "disk full"}], "owner": null
+        }));
+        assert_eq!(error.path, Some("events.1.level".to_owned()));
+        assert_eq!(error.message, "missing field `level`");
+
+        let error = incident_error(json!({
+            "title": "outage", "events": [{"title": "disk full", "level": 1, "size": 2}]
+        }));
+        assert_eq!(error.path, Some("events.0.size".to_owned()));
+    }
+
+    #[test]
+    fn valid_bodies_are_read() {
+        let incident: Incident = errors::from_value(&json!({
+            "title": "outage", "events": [{"title": "disk full", "level": 1}], "owner": null
+        })).unwrap();
+        assert_eq!(incident.events[0].level, 1);
+        assert!(incident.owner.is_none());
+    }
+
+    #[test]
+    fn truncated_bodies_are_syntax_errors() {
+        let error = body_error(r#"{"title": "disk full""#);
+        assert!(error.syntax);
+        assert_eq!(error.line, Some(1));
+
+        let body = error.body();
+        assert_eq!(body["code"], json!(400));
+        assert_eq!(body["errno"], json!(106));
+        assert_eq!(body["message"], json!("EOF while parsing an object"));
+        assert_eq!(body["details"]["path"], json!(null));
+    }
+}
+
+
+mod validation {
+    use rocket::http::Status;
+
+    use validate::{self, ValidationErrors};
+
+
+    #[test]
+    fn length_bounds_are_included() {
+        let title = "disk".to_owned();
+        assert!(validate::length(&title, Some(4), Some(4)).is_ok());
+        assert_eq!(validate::length(&title, Some(5), None),
+                   Err("Length must be at least 5.".to_owned()));
+        assert_eq!(validate::length(&title, None, Some(3)),
+                   Err("Length must be at most 3.".to_owned()));
+    }
+
+    #[test]
+    fn length_counts_characters_and_items() {
+        assert!(validate::length(&"été".to_owned(), None, Some(3)).is_ok());
+        assert!(validate::length(&vec![1, 2], Some(2), Some(2)).is_ok());
+        assert!(validate::length(&json!({"a": 1, "b": 2}), None, Some(1)).is_err());
+    }
+
+    #[test]
+    fn range_bounds_are_included() {
+        assert!(validate::range(0.0, Some(0.0), Some(100.0)).is_ok());
+        assert!(validate::range(100.0, Some(0.0), Some(100.0)).is_ok());
+        assert_eq!(validate::range(-0.5, Some(0.0), None), Err("Must be at least 0.".to_owned()));
+        assert_eq!(validate::range(100.5, None, Some(100.0)),
+                   Err("Must be at most 100.".to_owned()));
+    }
+
+    #[test]
+    fn regex_matches_values() {
+        assert!(validate::regex("disk", "^[a-z]+$").is_ok());
+        assert_eq!(validate::regex("Disk", "^[a-z]+$"), Err("Must match `^[a-z]+$`.".to_owned()));
+    }
+
+    #[test]
+    fn regex_patterns_are_cached() {
+        // Compiled patterns are reused, and invalid ones are never cached.
+        for _ in 0..2 {
+            assert!(validate::regex("level", "^l").is_ok());
+            assert!(validate::regex("error", "^l").is_err());
+            assert!(validate::regex("level", "(").unwrap_err().starts_with("Invalid pattern `(`"));
+        }
+    }
+
+    #[test]
+    fn one_of_compares_as_text() {
+        assert!(validate::one_of("low", &["low", "high"]).is_ok());
+        assert!(validate::one_of(&2, &["1", "2"]).is_ok());
+        assert_eq!(validate::one_of("Low", &["low", "high"]),
+                   Err("Must be one of: low, high.".to_owned()));
+    }
+
+    #[test]
+    fn errors_are_listed_by_field() {
+        let mut errors = ValidationErrors::new();
+        errors.check("title", validate::length(&"".to_owned(), Some(1), None));
+        errors.check("title", validate::regex("", "^[a-z]+$"));
+        errors.check("level", validate::one_of("fatal", &["low", "high"]));
+        errors.check("score", validate::range(50.0, Some(0.0), Some(100.0)));
+
+        let response = errors.into_result().unwrap_err().response();
+        assert_eq!(response.0, Status::UnprocessableEntity);
+        let body = (response.1).0;
+        assert_eq!(body["code"], json!(422));
+        assert_eq!(body["errno"], json!(109));
+        assert_eq!(body["message"], json!("Invalid fields: level, title."));
+        assert_eq!(body["details"]["fields"], json!({
+            "level": ["Must be one of: low, high."],
+            "title": ["Length must be at least 1.", "Must match `^[a-z]+$`."]
+        }));
+
+        assert!(ValidationErrors::new().into_result().is_ok());
+    }
+}
+
+
+mod queue {
+    use queue;
+
+
+    #[test]
+    fn synced_records_keep_their_provisional_ids() {
+        let queued = r#"{"id": -3, "title": "disk full"}"#;
+        let stored = json!({"id": 42, "title": "disk full"});
+        assert_eq!(queue::synced_id(queued, &stored, Some("id")), Some((-3, 42)));
+    }
+
+    #[test]
+    fn only_serial_keys_are_provisional() {
+        let queued = r#"{"uuid": "f00d", "title": "disk full"}"#;
+        let stored = json!({"uuid": "f00d", "title": "disk full"});
+        assert_eq!(queue::synced_id(queued, &stored, None), None);
+        assert_eq!(queue::synced_id("not json", &stored, Some("id")), None);
+    }
+}
+
+
+mod batch {
+    use std::collections::HashMap;
+
+    use rocket_contrib::JsonValue;
+
+    use batch::{BatchRoutes, SubRequest, SubResponse};
+
+
+    fn handled_by(endpoint: &str, request: &SubRequest) -> SubResponse {
+        JsonValue(json!({"endpoint": endpoint, "path": request.path})).into()
+    }
+
+    fn root(_: &(), request: &SubRequest) -> SubResponse {
+        handled_by("/", request)
+    }
+
+    fn warnings(_: &(), request: &SubRequest) -> SubResponse {
+        handled_by("/warning", request)
+    }
+
+    fn archived_warnings(_: &(), request: &SubRequest) -> SubResponse {
+        handled_by("/warning/archive", request)
+    }
+
+
+    fn dispatch(routes: &BatchRoutes<()>, path: &str) -> SubResponse {
+        let request = SubRequest {
+            method: "GET".to_owned(),
+            path: path.to_owned(),
+            body: None,
+            headers: HashMap::new(),
+        };
+        routes.dispatch(&(), &request)
+    }
+
+
+    #[test]
+    fn paths_are_relative_to_the_longest_endpoint() {
+        let routes = BatchRoutes::new()
+            .add("/", root)
+            .add("/warning/archive", archived_warnings)
+            .add("/warning", warnings);
+
+        let response = dispatch(&routes, "/warning/archive/3");
+        assert_eq!(response.body, json!({"endpoint": "/warning/archive", "path": "/3"}));
+        let response = dispatch(&routes, "/warning/3?_fields=title");
+        assert_eq!(response.body, json!({"endpoint": "/warning", "path": "/3?_fields=title"}));
+        let response = dispatch(&routes, "/warning?_limit=1");
+        assert_eq!(response.body, json!({"endpoint": "/warning", "path": "?_limit=1"}));
+        let response = dispatch(&routes, "/warning");
+        assert_eq!(response.body, json!({"endpoint": "/warning", "path": ""}));
+    }
+
+    #[test]
+    fn endpoints_only_match_whole_segments() {
+        let routes = BatchRoutes::new().add("/", root).add("/warning", warnings);
+
+        let response = dispatch(&routes, "/warnings/3");
+        assert_eq!(response.body, json!({"endpoint": "/", "path": "/warnings/3"}));
+    }
+
+    #[test]
+    fn unknown_paths_are_not_found() {
+        let routes = BatchRoutes::new().add("/warning/", warnings);
+
+        assert_eq!(dispatch(&routes, "/warning/3").body["endpoint"], json!("/warning"));
+        let response = dispatch(&routes, "/event/3");
+        assert_eq!(response.status, 404);
+        assert_eq!(response.body["errno"], json!(111));
+    }
+}
+
+
+mod search {
+    use search;
+
+
+    #[test]
+    fn sql_searches_every_field() {
+        assert_eq!(search::sql(&["title", "body"], "it's"), concat!(
+            "to_tsvector('english', coalesce(\"title\", '') || ' ' || coalesce(\"body\", '')) ",
+            "@@ plainto_tsquery('english', 'it''s')"
+        ));
+    }
+
+    #[test]
+    fn fallback_sql_needs_every_term() {
+        assert_eq!(search::fallback_sql(&["title", "body"], "Disk  full"), concat!(
+            "(\"title\" ILIKE '%disk%' OR \"body\" ILIKE '%disk%') AND ",
+            "(\"title\" ILIKE '%full%' OR \"body\" ILIKE '%full%')"
+        ));
+        assert_eq!(search::fallback_sql(&["title"], "  "), "TRUE");
+    }
+
+    #[test]
+    fn fallback_sql_escapes_wildcards_and_quotes() {
+        assert_eq!(search::fallback_sql(&["title"], r"100%_it's\"),
+                   r#"("title" ILIKE '%100\%\_it''s\\%')"#);
+    }
+
+    #[test]
+    fn matches_queued_records_like_fallback_sql() {
+        let values = [Some("Disk full"), None, Some("on /var")];
+        assert!(search::matches(&values, "disk VAR"));
+        assert!(search::matches(&values, ""));
+        assert!(!search::matches(&values, "disk tmp"));
+        // Wildcards are matched literally.
+        assert!(!search::matches(&values, "d%k"));
+        assert!(search::matches(&[Some("100% full")], "100%"));
+    }
+}
+
+
+mod preconditions {
+    use rocket::http::Status;
+
+    use headers::{self, Preconditions};
+
+
+    fn preconditions(if_match: Option<&str>, if_none_match: Option<&str>) -> Preconditions {
+        Preconditions {
+            if_match: if_match.map(|value| value.to_owned()),
+            if_none_match: if_none_match.map(|value| value.to_owned()),
+        }
+    }
+
+
+    #[test]
+    fn if_match_needs_a_matching_record() {
+        let if_match = preconditions(Some("\"1\", \"2\""), None);
+        assert_eq!(if_match.check(Some("\"2\"")), Ok(()));
+        assert_eq!(if_match.check(Some("\"3\"")), Err(Status::PreconditionFailed));
+        assert_eq!(if_match.check(None), Err(Status::PreconditionFailed));
+
+        let any = preconditions(Some("*"), None);
+        assert_eq!(any.check(Some("\"3\"")), Ok(()));
+        assert_eq!(any.check(None), Err(Status::PreconditionFailed));
+    }
+
+    #[test]
+    fn if_none_match_refuses_matching_records() {
+        let create_only = preconditions(None, Some(" * "));
+        assert!(create_only.is_create_only());
+        assert_eq!(create_only.check(None), Ok(()));
+        assert_eq!(create_only.check(Some("\"1\"")), Err(Status::PreconditionFailed));
+
+        let if_none_match = preconditions(None, Some("\"1\""));
+        assert!(!if_none_match.is_create_only());
+        assert_eq!(if_none_match.check(Some("\"2\"")), Ok(()));
+        assert_eq!(if_none_match.check(Some("\"1\"")), Err(Status::PreconditionFailed));
+    }
+
+    #[test]
+    fn weak_tags_compare_as_strong_ones() {
+        assert_eq!(preconditions(Some("W/\"1\""), None).check(Some("\"1\"")), Ok(()));
+
+        let if_none_match = preconditions(None, Some("\"0\", W/\"1\""));
+        assert!(if_none_match.is_not_modified("\"1\""));
+        assert!(!if_none_match.is_not_modified("\"2\""));
+        assert!(!preconditions(Some("\"1\""), None).is_not_modified("\"1\""));
+        assert!(preconditions(None, Some("*")).is_not_modified("\"2\""));
+    }
+
+    #[test]
+    fn content_etags_are_stable() {
+        assert_eq!(headers::content_etag(&json!({"title": "disk", "id": 1})),
+                   "\"c58402531fc52b3e\"");
+        assert_eq!(headers::content_etag(&"a"), "\"af63dc4c8601ec8c\"");
+    }
+
+    #[test]
+    fn no_headers_let_everything_through() {
+        let none = preconditions(None, None);
+        assert!(none.is_empty());
+        assert!(!none.is_create_only());
+        assert_eq!(none.check(None), Ok(()));
+        assert_eq!(none.check(Some("\"1\"")), Ok(()));
+    }
+}
+
+
+mod storage_errors {
+    use diesel::result::{DatabaseErrorKind, Error};
+    use rocket::http::Status;
+
+    use storage::ResourceStorageError;
+
+
+    fn database_error(kind: DatabaseErrorKind) -> ResourceStorageError {
+        ResourceStorageError::from(Error::DatabaseError(kind, Box::new("violated".to_owned())))
+    }
+
+
+    #[test]
+    fn constraint_violations_are_conflicts() {
+        for kind in vec![DatabaseErrorKind::UniqueViolation,
+                         DatabaseErrorKind::ForeignKeyViolation] {
+            let error = database_error(kind);
+            assert_eq!(error.status(), Status::Conflict);
+            assert_eq!(error.body()["errno"], json!(122));
+        }
+    }
+
+    #[test]
+    fn unreachable_databases_are_unavailable() {
+        let error = database_error(DatabaseErrorKind::UnableToSendCommand);
+        assert_eq!(error.status(), Status::ServiceUnavailable);
+        assert_eq!(ResourceStorageError::from(Error::NotFound).status(), Status::NotFound);
+    }
+}
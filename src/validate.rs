@@ -0,0 +1,179 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use regex::Regex;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket_contrib::JsonValue;
+use serde_json;
+
+use errors::{self, Errno};
+
+
+/// Errors of the fields of a request body, by field name. Rendered as a
+/// `422 Unprocessable Entity` response.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ValidationErrors {
+    pub fields: BTreeMap<String, Vec<String>>,
+}
+
+
+impl ValidationErrors {
+    pub fn new() -> ValidationErrors {
+        ValidationErrors::default()
+    }
+
+    pub fn add(&mut self, field: &str, message: String) {
+        self.fields.entry(field.to_owned()).or_insert_with(Vec::new).push(message);
+    }
+
+    /// Records the error of a single check, if any.
+    pub fn check(&mut self, field: &str, result: Result<(), String>) {
+        if let Err(message) = result {
+            self.add(field, message);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+
+    pub fn message(&self) -> String {
+        let names: Vec<&str> = self.fields.keys().map(|name| name.as_str()).collect();
+        format!("Invalid fields: {}.", names.join(", "))
+    }
+
+    /// Renders the errors with the body shared by every error response,
+    /// along with extra `details`.
+    pub fn body_with(&self, mut details: serde_json::Value) -> serde_json::Value {
+        details["fields"] = json!(self.fields);
+        errors::body(Status::UnprocessableEntity, Errno::InvalidPostedData, &self.message(),
+                     details)
+    }
+
+    pub fn body(&self) -> serde_json::Value {
+        self.body_with(json!({}))
+    }
+
+    pub fn response(&self) -> Custom<JsonValue> {
+        Custom(Status::UnprocessableEntity, JsonValue(self.body()))
+    }
+}
+
+
+impl From<ValidationErrors> for Custom<JsonValue> {
+    fn from(errors: ValidationErrors) -> Custom<JsonValue> {
+        errors.response()
+    }
+}
+
+
+/// Values checked by `#[validate(length(...))]`: characters of strings and
+/// items of lists.
+pub trait Length {
+    fn length(&self) -> usize;
+}
+
+
+impl Length for String {
+    fn length(&self) -> usize {
+        self.chars().count()
+    }
+}
+
+
+impl<T> Length for Vec<T> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+
+impl Length for serde_json::Value {
+    fn length(&self) -> usize {
+        match *self {
+            serde_json::Value::String(ref value) => value.chars().count(),
+            serde_json::Value::Array(ref items) => items.len(),
+            serde_json::Value::Object(ref members) => members.len(),
+            _ => 0,
+        }
+    }
+}
+
+
+/// Checks `#[validate(length(min = 1, max = 255))]`.
+pub fn length<T: Length + ?Sized>(value: &T, min: Option<usize>, max: Option<usize>)
+        -> Result<(), String> {
+    let length = value.length();
+    if let Some(min) = min {
+        if length < min {
+            return Err(format!("Length must be at least {}.", min));
+        }
+    }
+    if let Some(max) = max {
+        if length > max {
+            return Err(format!("Length must be at most {}.", max));
+        }
+    }
+    Ok(())
+}
+
+
+/// Checks `#[validate(range(min = 0, max = 100))]`, bounds included.
+pub fn range(value: f64, min: Option<f64>, max: Option<f64>) -> Result<(), String> {
+    if let Some(min) = min {
+        if value < min {
+            return Err(format!("Must be at least {}.", min));
+        }
+    }
+    if let Some(max) = max {
+        if value > max {
+            return Err(format!("Must be at most {}.", max));
+        }
+    }
+    Ok(())
+}
+
+
+thread_local! {
+    static PATTERNS: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+}
+
+
+/// Checks `#[validate(regex = "^[a-z]+$")]`. Patterns are checked when
+/// deriving `Resource`, and compiled once per thread.
+pub fn regex(value: &str, pattern: &str) -> Result<(), String> {
+    let matched = PATTERNS.with(|patterns| {
+        let mut patterns = patterns.borrow_mut();
+        if !patterns.contains_key(pattern) {
+            let regex = Regex::new(pattern)
+                .map_err(|err| format!("Invalid pattern `{}`: {}.", pattern, err))?;
+            patterns.insert(pattern.to_owned(), regex);
+        }
+        Ok(patterns[pattern].is_match(value))
+    })?;
+    if matched {
+        Ok(())
+    } else {
+        Err(format!("Must match `{}`.", pattern))
+    }
+}
+
+
+/// Checks `#[validate(one_of("low", "high"))]`, comparing the value as text.
+pub fn one_of<T: ToString + ?Sized>(value: &T, choices: &[&str]) -> Result<(), String> {
+    let value = value.to_string();
+    if choices.iter().any(|choice| *choice == value) {
+        Ok(())
+    } else {
+        Err(format!("Must be one of: {}.", choices.join(", ")))
+    }
+}